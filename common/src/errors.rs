@@ -0,0 +1,132 @@
+use actix_web::error::{BlockingError, QueryPayloadError};
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+
+use super::validation::FieldErrors;
+
+use diesel::r2d2::PoolError;
+use diesel::result::{DatabaseErrorKind, Error};
+
+use serde_json::json;
+
+use std::{fmt, io};
+
+/// Seconds a client should wait before retrying a 503.
+pub const RETRY_AFTER_SECS: u64 = 5;
+
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    NotFound(String),
+    Conflict(String),
+    UnprocessableEntity(String),
+    Invalid(FieldErrors),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    ServiceUnavailable(String),
+    Internal(String),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(msg)
+            | ApiError::NotFound(msg)
+            | ApiError::Conflict(msg)
+            | ApiError::UnprocessableEntity(msg)
+            | ApiError::PayloadTooLarge(msg)
+            | ApiError::UnsupportedMediaType(msg)
+            | ApiError::ServiceUnavailable(msg)
+            | ApiError::Internal(msg) => write!(f, "{}", msg),
+            ApiError::Invalid(errors) => write!(f, "{}", errors),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::UnprocessableEntity(_) | ApiError::Invalid(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let mut resp = HttpResponse::build(status);
+        if let ApiError::ServiceUnavailable(_) = self {
+            resp.set_header(header::RETRY_AFTER, RETRY_AFTER_SECS.to_string());
+        }
+        if let ApiError::Invalid(errors) = self {
+            return resp.json(json!({
+                "status": status.as_u16(),
+                "message": "Validation failed.",
+                "errors": errors.get_errors(),
+            }));
+        }
+        resp.json(json!({"status": status.as_u16(), "message": self.to_string()}))
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::NotFound => ApiError::NotFound("Resource not found.".to_owned()),
+            Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+                ApiError::Conflict(info.message().to_owned())
+            }
+            Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, info) => {
+                ApiError::UnprocessableEntity(info.message().to_owned())
+            }
+            Error::QueryBuilderError(e) => ApiError::BadRequest(e.to_string()),
+            e => ApiError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl From<FieldErrors> for ApiError {
+    fn from(e: FieldErrors) -> Self {
+        ApiError::Invalid(e)
+    }
+}
+
+impl From<io::Error> for ApiError {
+    fn from(e: io::Error) -> Self {
+        ApiError::Internal(format!("Could not access stored file: {}", e))
+    }
+}
+
+impl From<PoolError> for ApiError {
+    fn from(e: PoolError) -> Self {
+        ApiError::ServiceUnavailable(format!("Could not get connection from pool: {}", e))
+    }
+}
+
+impl<E> From<BlockingError<E>> for ApiError
+where
+    E: Into<ApiError> + fmt::Debug,
+{
+    fn from(e: BlockingError<E>) -> Self {
+        match e {
+            BlockingError::Error(e) => e.into(),
+            BlockingError::Canceled => {
+                ApiError::ServiceUnavailable("Database worker pool is unavailable.".to_owned())
+            }
+        }
+    }
+}
+
+/// Error handler for `web::QueryConfig`, so malformed query strings (unknown
+/// keys, bad enum values, non-numeric bounds) get the same JSON body as every
+/// other error.
+pub fn query_error_handler(err: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::BadRequest(err.to_string()).into()
+}
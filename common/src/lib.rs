@@ -0,0 +1,9 @@
+use diesel::pg::PgConnection;
+use diesel::r2d2::{self, ConnectionManager};
+
+pub mod errors;
+pub mod payload;
+pub mod pool;
+pub mod validation;
+
+pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
@@ -0,0 +1,85 @@
+use super::errors::ApiError;
+
+use actix_web::dev::Payload;
+use actix_web::error::QueryPayloadError;
+use actix_web::web::{Bytes, Query};
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+
+use serde::de::DeserializeOwned;
+
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+
+/// Request body extractor that accepts either `application/json` or
+/// `application/x-www-form-urlencoded`, picked by `Content-Type`. Any other
+/// media type is rejected with a 415, and bodies that fail to deserialize get
+/// a 400 naming the offending field.
+pub struct FormOrJson<T>(T);
+
+impl<T> FormOrJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for FormOrJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+enum Format {
+    Json,
+    Form,
+}
+
+fn parse<T: DeserializeOwned>(format: Format, body: &[u8]) -> Result<T, ApiError> {
+    match format {
+        Format::Json => serde_json::from_slice(body)
+            .map_err(|e| ApiError::BadRequest(format!("Invalid JSON body: {}", e))),
+        Format::Form => {
+            let body = std::str::from_utf8(body)
+                .map_err(|_| ApiError::BadRequest("Form body is not valid UTF-8.".to_owned()))?;
+            Query::<T>::from_query(body).map(Query::into_inner).map_err(
+                |QueryPayloadError::Deserialize(e)| {
+                    ApiError::BadRequest(format!("Invalid form body: {}", e))
+                },
+            )
+        }
+    }
+}
+
+impl<T> FromRequest for FormOrJson<T>
+where
+    T: DeserializeOwned + 'static,
+{
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = Result<FormOrJson<T>, ApiError>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let format = match req.content_type() {
+            "application/json" => Format::Json,
+            "application/x-www-form-urlencoded" => Format::Form,
+            other => {
+                let err = ApiError::UnsupportedMediaType(format!(
+                    "Unsupported content type `{}`; expected `application/json` or \
+                     `application/x-www-form-urlencoded`.",
+                    other
+                ));
+                return Box::pin(async move { Err(err) });
+            }
+        };
+        let body = Bytes::from_request(req, payload);
+
+        Box::pin(async move {
+            let body = body
+                .await
+                .map_err(|e| ApiError::BadRequest(format!("Could not read body: {}", e)))?;
+            parse(format, &body).map(FormOrJson)
+        })
+    }
+}
@@ -0,0 +1,104 @@
+use super::errors::ApiError;
+use super::DbPool;
+
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest};
+
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, Pool, PoolError, PooledConnection};
+
+use std::env;
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    pub connection_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_size: 10,
+            min_idle: None,
+            connection_timeout: Duration::from_secs(5),
+            idle_timeout: Some(Duration::from_secs(600)),
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Reads `DATABASE_POOL_MAX_SIZE`, `DATABASE_POOL_MIN_IDLE`,
+    /// `DATABASE_POOL_CONNECTION_TIMEOUT` and `DATABASE_POOL_IDLE_TIMEOUT`
+    /// (timeouts in seconds), falling back to the defaults for unset values.
+    pub fn from_env() -> Self {
+        let default = PoolConfig::default();
+        PoolConfig {
+            max_size: env_var("DATABASE_POOL_MAX_SIZE").unwrap_or(default.max_size),
+            min_idle: env_var("DATABASE_POOL_MIN_IDLE").or(default.min_idle),
+            connection_timeout: env_var("DATABASE_POOL_CONNECTION_TIMEOUT")
+                .map(Duration::from_secs)
+                .unwrap_or(default.connection_timeout),
+            idle_timeout: env_var("DATABASE_POOL_IDLE_TIMEOUT")
+                .map(Duration::from_secs)
+                .or(default.idle_timeout),
+        }
+    }
+
+    pub fn build(&self, database_url: &str) -> Result<DbPool, PoolError> {
+        let manager = ConnectionManager::<PgConnection>::new(database_url);
+        Pool::builder()
+            .max_size(self.max_size)
+            .min_idle(self.min_idle)
+            .connection_timeout(self.connection_timeout)
+            .idle_timeout(self.idle_timeout)
+            .build(manager)
+    }
+}
+
+fn env_var<T: FromStr>(key: &str) -> Option<T> {
+    env::var(key).ok().and_then(|v| v.parse().ok())
+}
+
+/// A connection checked out of the app's `DbPool`. Fails the request with a
+/// 503 instead of panicking when the pool is exhausted or the database is down.
+/// The checkout can wait up to the pool's `connection_timeout`, so it runs on
+/// the blocking thread pool rather than stalling the worker.
+pub struct DbConn(PooledConnection<ConnectionManager<PgConnection>>);
+
+impl Deref for DbConn {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        &self.0
+    }
+}
+
+impl FromRequest for DbConn {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = Result<DbConn, ApiError>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let pool = match req.app_data::<web::Data<DbPool>>() {
+            Some(pool) => pool.clone(),
+            None => {
+                let err = ApiError::Internal("Database pool is not configured.".to_owned());
+                return Box::pin(async move { Err(err) });
+            }
+        };
+
+        Box::pin(async move {
+            web::block(move || pool.get())
+                .await
+                .map(DbConn)
+                .map_err(ApiError::from)
+        })
+    }
+}
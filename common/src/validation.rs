@@ -0,0 +1,90 @@
+use serde::Serialize;
+
+use std::fmt;
+
+/// Longest product name the `products.name` column accepts.
+pub const MAX_NAME_LEN: usize = 128;
+
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    field: &'static str,
+    message: String,
+}
+
+/// Every problem found while cleaning one input, reported field by field
+/// as a 422.
+#[derive(Debug, Default, Serialize)]
+pub struct FieldErrors {
+    errors: Vec<FieldError>,
+}
+
+impl FieldErrors {
+    pub fn add(&mut self, field: &'static str, message: &str) {
+        self.errors.push(FieldError {
+            field,
+            message: message.to_owned(),
+        });
+    }
+
+    pub fn name(&mut self, field: &'static str, value: &str) {
+        match value.trim().chars().count() {
+            0 => self.add(field, "must not be empty."),
+            n if n > MAX_NAME_LEN => self.add(
+                field,
+                &format!("must be at most {} characters.", MAX_NAME_LEN),
+            ),
+            _ => (),
+        }
+    }
+
+    pub fn required(&mut self, field: &'static str, value: &str) {
+        if value.trim().is_empty() {
+            self.add(field, "must not be empty.");
+        }
+    }
+
+    pub fn percentage(&mut self, field: &'static str, value: f32) {
+        if !value.is_finite() || !(0.0..=100.0).contains(&value) {
+            self.add(field, "must be a percentage between 0 and 100.");
+        }
+    }
+
+    pub fn non_negative(&mut self, field: &'static str, value: i32) {
+        if value < 0 {
+            self.add(field, "must not be negative.");
+        }
+    }
+
+    pub fn positive<T: PartialOrd + Default>(&mut self, field: &'static str, value: T) {
+        if value.partial_cmp(&T::default()) != Some(std::cmp::Ordering::Greater) {
+            self.add(field, "must be greater than zero.");
+        }
+    }
+
+    pub fn get_errors(&self) -> &Vec<FieldError> {
+        &self.errors
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// `Ok(value)` when nothing was reported, otherwise the collected errors.
+    pub fn into_result<T>(self, value: T) -> Result<T, FieldErrors> {
+        match self.is_empty() {
+            true => Ok(value),
+            false => Err(self),
+        }
+    }
+}
+
+impl fmt::Display for FieldErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let errors = self
+            .errors
+            .iter()
+            .map(|e| format!("`{}` {}", e.field, e.message))
+            .collect::<Vec<_>>();
+        write!(f, "{}", errors.join(" "))
+    }
+}
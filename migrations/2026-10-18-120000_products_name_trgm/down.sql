@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX products_name_trgm_idx;
+DROP EXTENSION IF EXISTS pg_trgm;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE inventories ADD COLUMN price FLOAT4;
+UPDATE inventories SET price = price_cents / 100.0;
+ALTER TABLE inventories ALTER COLUMN price SET NOT NULL;
+ALTER TABLE inventories DROP COLUMN currency;
+ALTER TABLE inventories DROP COLUMN price_cents;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE inventories ADD COLUMN price_cents BIGINT;
+UPDATE inventories SET price_cents = ROUND(price::numeric * 100);
+ALTER TABLE inventories ALTER COLUMN price_cents SET NOT NULL;
+ALTER TABLE inventories ADD COLUMN currency VARCHAR(3) NOT NULL DEFAULT 'USD';
+ALTER TABLE inventories DROP COLUMN price;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE inventories DROP COLUMN net_weight_unit;
+DROP TYPE weight_unit;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+CREATE TYPE weight_unit AS ENUM('mg', 'g', 'eighth', 'quarter', 'half', 'oz');
+
+ALTER TABLE inventories ADD COLUMN net_weight_unit WEIGHT_UNIT NOT NULL DEFAULT 'g';
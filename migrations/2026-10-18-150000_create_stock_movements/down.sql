@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE stock_movements;
+DROP FUNCTION stock_movements_append_only();
+DROP TYPE movement_kind;
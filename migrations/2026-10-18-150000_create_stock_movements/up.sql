@@ -0,0 +1,31 @@
+-- Your SQL goes here
+CREATE TYPE movement_kind AS ENUM('receipt', 'sale', 'adjustment', 'waste', 'transfer');
+
+-- `user_id` refers to the users service, which keeps its own database, so it
+-- cannot be a foreign key. It is NULL for movements the system records itself.
+-- `inventory_id` is not a foreign key either: the ledger is the audit trail of
+-- an inventory item and has to outlive it, so deleting the item keeps its rows.
+CREATE TABLE stock_movements (
+    id SERIAL PRIMARY KEY,
+    inventory_id INT NOT NULL,
+    kind MOVEMENT_KIND NOT NULL,
+    quantity INT NOT NULL CHECK (quantity <> 0),
+    balance INT NOT NULL,
+    reason TEXT NOT NULL,
+    user_id INT,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX stock_movements_inventory_id_idx ON stock_movements (inventory_id, id);
+
+CREATE FUNCTION stock_movements_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'stock_movements is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER stock_movements_no_update BEFORE UPDATE OR DELETE ON stock_movements
+    FOR EACH ROW EXECUTE PROCEDURE stock_movements_append_only();
+
+INSERT INTO stock_movements (inventory_id, kind, quantity, balance, reason)
+    SELECT id, 'adjustment', stock, stock, 'Opening balance' FROM inventories WHERE stock <> 0;
@@ -0,0 +1,29 @@
+-- Your SQL goes here
+CREATE TYPE order_status AS ENUM('pending', 'paid', 'fulfilled', 'cancelled');
+
+-- `user_id` is the customer's id in the users service, which keeps its own
+-- database, so it cannot be a foreign key.
+CREATE TABLE orders (
+    id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL,
+    status ORDER_STATUS NOT NULL DEFAULT 'pending',
+    total_cents BIGINT NOT NULL,
+    currency VARCHAR(3) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- Sold inventory cannot be deleted out from under its order history.
+CREATE TABLE order_lines (
+    id SERIAL PRIMARY KEY,
+    order_id INT NOT NULL,
+    inventory_id INT NOT NULL,
+    quantity INT NOT NULL CHECK (quantity > 0),
+    unit_price_cents BIGINT NOT NULL,
+    line_total_cents BIGINT NOT NULL,
+    FOREIGN KEY (order_id) REFERENCES orders (id) ON DELETE CASCADE,
+    FOREIGN KEY (inventory_id) REFERENCES inventories (id) ON DELETE RESTRICT
+);
+
+CREATE INDEX order_lines_order_id_idx ON order_lines (order_id);
+CREATE INDEX order_lines_inventory_id_idx ON order_lines (inventory_id);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE inventories ADD COLUMN reorder_threshold INT NOT NULL DEFAULT 0 CHECK (reorder_threshold >= 0);
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- Each row is the price of an inventory item from `effective_from` until the
+-- next row's `effective_from`. Rows in the future are scheduled changes.
+-- Like `stock_movements`, the timeline is kept for auditing margins after the
+-- item is deleted, so `inventory_id` is not a foreign key and each row carries
+-- the item's `product_id`.
+CREATE TABLE price_history (
+    id SERIAL PRIMARY KEY,
+    inventory_id INT NOT NULL,
+    product_id INT NOT NULL,
+    price_cents BIGINT NOT NULL CHECK (price_cents > 0),
+    currency VARCHAR(3) NOT NULL,
+    effective_from TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX price_history_inventory_id_idx ON price_history (inventory_id, effective_from);
+
+INSERT INTO price_history (inventory_id, product_id, price_cents, currency, effective_from)
+    SELECT id, product_id, price_cents, currency, NOW() FROM inventories;
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- Strain lineage: each row says `parent_id` is a parent strain of `child_id`.
+-- The graph must stay acyclic; that is checked when parents are assigned.
+CREATE TABLE cannabis_parents (
+    child_id INT NOT NULL,
+    parent_id INT NOT NULL,
+    PRIMARY KEY (child_id, parent_id),
+    CHECK (child_id <> parent_id),
+    FOREIGN KEY (child_id) REFERENCES cannabis (id) ON DELETE CASCADE,
+    FOREIGN KEY (parent_id) REFERENCES cannabis (id) ON DELETE CASCADE
+);
+
+CREATE INDEX cannabis_parents_parent_id_idx ON cannabis_parents (parent_id);
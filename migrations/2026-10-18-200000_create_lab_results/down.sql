@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE lab_results;
+DROP TYPE test_outcome;
@@ -0,0 +1,30 @@
+-- Your SQL goes here
+CREATE TYPE test_outcome AS ENUM('pass', 'fail');
+
+-- One certificate of analysis (COA) per row. Cannabinoids and terpenes are
+-- measured percentages; `coa_path` points at the original document on the
+-- local filesystem once it has been uploaded.
+CREATE TABLE lab_results (
+    id SERIAL PRIMARY KEY,
+    batch_id INT NOT NULL,
+    lab_name VARCHAR(128) NOT NULL,
+    tested_on DATE NOT NULL,
+    thc REAL NOT NULL,
+    cbd REAL NOT NULL,
+    total_cannabinoids REAL NOT NULL,
+    myrcene REAL NOT NULL DEFAULT 0,
+    pinene REAL NOT NULL DEFAULT 0,
+    limonene REAL NOT NULL DEFAULT 0,
+    caryophyllene REAL NOT NULL DEFAULT 0,
+    terpinolene REAL NOT NULL DEFAULT 0,
+    pesticides TEST_OUTCOME NOT NULL,
+    heavy_metals TEST_OUTCOME NOT NULL,
+    microbials TEST_OUTCOME NOT NULL,
+    residual_solvents TEST_OUTCOME NOT NULL,
+    coa_path TEXT,
+    coa_content_type VARCHAR(127),
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (batch_id) REFERENCES batches (id) ON DELETE CASCADE
+);
+
+CREATE INDEX lab_results_batch_id_idx ON lab_results (batch_id);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE inventories DROP COLUMN recalled, DROP COLUMN batch_id;
+ALTER TABLE batches DROP COLUMN recall_reason, DROP COLUMN recalled_at;
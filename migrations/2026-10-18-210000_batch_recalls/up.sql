@@ -0,0 +1,13 @@
+-- Your SQL goes here
+ALTER TABLE batches
+    ADD COLUMN recalled_at TIMESTAMP,
+    ADD COLUMN recall_reason TEXT;
+
+-- Inventory can name the batch it was packed from, so a recall knows which
+-- stock to pull. `recalled` locks the row out of listings and checkout.
+ALTER TABLE inventories
+    ADD COLUMN batch_id INT,
+    ADD COLUMN recalled BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD FOREIGN KEY (batch_id) REFERENCES batches (id) ON DELETE SET NULL;
+
+CREATE INDEX inventories_batch_id_idx ON inventories (batch_id);
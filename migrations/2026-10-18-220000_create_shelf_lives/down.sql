@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP FUNCTION batch_expired(INT);
+DROP FUNCTION batch_expires_on(INT);
+DROP TABLE shelf_lives;
@@ -0,0 +1,28 @@
+-- Your SQL goes here
+-- Days stock of each category keeps after `batches.package_date`. A category
+-- without a row never expires.
+CREATE TABLE shelf_lives (
+    category CATEGORY PRIMARY KEY,
+    days INT NOT NULL CHECK (days > 0)
+);
+
+INSERT INTO shelf_lives (category, days) VALUES
+    ('flower', 365),
+    ('pre_roll', 180),
+    ('edible', 180),
+    ('cartridge', 365),
+    ('extract', 365);
+
+-- Computed on every read so a changed shelf life applies to existing batches.
+CREATE FUNCTION batch_expires_on(batch INT) RETURNS DATE AS $$
+    SELECT b.package_date + s.days
+    FROM batches b
+    INNER JOIN cannabis c ON b.cannabis_id = c.id
+    INNER JOIN products p ON c.product_id = p.id
+    INNER JOIN shelf_lives s ON s.category = p.category
+    WHERE b.id = batch
+$$ LANGUAGE SQL STABLE;
+
+CREATE FUNCTION batch_expired(batch INT) RETURNS BOOLEAN AS $$
+    SELECT COALESCE(batch_expires_on(batch) < CURRENT_DATE, FALSE)
+$$ LANGUAGE SQL STABLE;
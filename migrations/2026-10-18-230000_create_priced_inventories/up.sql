@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- Inventory rows with the price that is in effect right now: the latest
+-- `price_history` row whose `effective_from` has passed. Scheduled changes
+-- show up here on time without anything writing to `inventories`.
+CREATE VIEW priced_inventories AS
+    SELECT i.id, i.product_id, i.stock, i.net_weight,
+        COALESCE(h.price_cents, i.price_cents) AS price_cents,
+        COALESCE(h.currency, i.currency) AS currency,
+        i.net_weight_unit, i.reorder_threshold, i.batch_id, i.recalled
+    FROM inventories i
+    LEFT JOIN LATERAL (
+        SELECT price_cents, currency FROM price_history
+        WHERE inventory_id = i.id AND effective_from <= NOW()
+        ORDER BY effective_from DESC, id DESC
+        LIMIT 1
+    ) h ON TRUE;
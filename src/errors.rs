@@ -0,0 +1,93 @@
+use super::models::{LineageError, OrderError, PotencyError, RecallError, StockError};
+
+use diesel::result::{DatabaseErrorKind, Error};
+
+pub use common::errors::{query_error_handler, ApiError, RETRY_AFTER_SECS};
+
+/// The foreign key that keeps inventory on the books once an order line
+/// refers to it.
+const SOLD_INVENTORY_FK: &str = "order_lines_inventory_id_fkey";
+
+impl From<StockError> for ApiError {
+    fn from(e: StockError) -> Self {
+        match e {
+            StockError::Insufficient {
+                inventory_id,
+                available,
+                requested,
+            } => ApiError::Conflict(format!(
+                "Inventory {} has only {} in stock; {} requested.",
+                inventory_id, available, requested
+            )),
+            StockError::Db(e) => e.into(),
+        }
+    }
+}
+
+impl From<OrderError> for ApiError {
+    fn from(e: OrderError) -> Self {
+        match e {
+            OrderError::Stock(e) => e.into(),
+            OrderError::Invalid(msg) => ApiError::UnprocessableEntity(msg),
+            OrderError::Transition { from, to } => {
+                ApiError::Conflict(format!("Cannot move an order from {:?} to {:?}.", from, to))
+            }
+            OrderError::Recalled { inventory_id } => ApiError::Conflict(format!(
+                "Inventory {} belongs to a recalled batch and cannot be sold.",
+                inventory_id
+            )),
+            OrderError::Expired { inventory_id } => ApiError::Conflict(format!(
+                "Inventory {} has passed its expiration date and cannot be sold.",
+                inventory_id
+            )),
+        }
+    }
+}
+
+impl From<LineageError> for ApiError {
+    fn from(e: LineageError) -> Self {
+        match e {
+            LineageError::Cycle { parent_id } => ApiError::UnprocessableEntity(format!(
+                "Strain {} descends from this strain, so it cannot be a parent.",
+                parent_id
+            )),
+            LineageError::Db(e) => e.into(),
+        }
+    }
+}
+
+impl From<PotencyError> for ApiError {
+    fn from(e: PotencyError) -> Self {
+        match e {
+            PotencyError::Invalid(errors) => errors.into(),
+            PotencyError::Db(e) => e.into(),
+        }
+    }
+}
+
+impl From<RecallError> for ApiError {
+    fn from(e: RecallError) -> Self {
+        match e {
+            RecallError::AlreadyRecalled { batch_id } => {
+                ApiError::Conflict(format!("Batch {} has already been recalled.", batch_id))
+            }
+            RecallError::Db(e) => e.into(),
+        }
+    }
+}
+
+/// Error mapping for deleting an inventory item, or a product along with its
+/// inventory: one that has been sold is a 409 rather than a generic 422.
+pub fn delete_error(e: Error) -> ApiError {
+    match e {
+        Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, info)
+            if info.constraint_name() == Some(SOLD_INVENTORY_FK) =>
+        {
+            ApiError::Conflict(
+                "Inventory that has been sold cannot be deleted; set its stock to 0 instead."
+                    .to_owned(),
+            )
+        }
+        e => e.into(),
+    }
+}
@@ -0,0 +1,138 @@
+use super::errors::ApiError;
+use super::models::*;
+use super::pagination::{load_page_with, PageParams, Sortable};
+use super::pool::DbConn;
+
+use actix_web::web::{self, Bytes};
+
+use diesel::pg::Pg;
+use diesel::query_source::QueryableByName;
+use diesel::sql_types::Nullable;
+
+use futures::stream::{self, Stream};
+
+use serde::{Deserialize, Serialize};
+
+/// Rows fetched per round trip while streaming an export.
+pub const EXPORT_BATCH_SIZE: i64 = 500;
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Jsonl => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+
+    /// Encodes one batch of rows. Only the first CSV batch carries a header.
+    fn encode<T: Serialize>(&self, rows: &[T], first: bool) -> Result<Bytes, ApiError> {
+        let internal = |e: &dyn std::fmt::Display| ApiError::Internal(e.to_string());
+        match self {
+            ExportFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(first)
+                    .from_writer(vec![]);
+                for row in rows {
+                    writer.serialize(row).map_err(|e| internal(&e))?;
+                }
+                writer
+                    .into_inner()
+                    .map(Bytes::from)
+                    .map_err(|e| internal(&e))
+            }
+            ExportFormat::Jsonl => {
+                let mut buf = vec![];
+                for row in rows {
+                    serde_json::to_writer(&mut buf, row).map_err(|e| internal(&e))?;
+                    buf.push(b'\n');
+                }
+                Ok(Bytes::from(buf))
+            }
+        }
+    }
+}
+
+/// Query string accepted by the `/exports/*` routes.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportParams {
+    #[serde(default)]
+    pub format: ExportFormat,
+    pub category: Option<Category>,
+}
+
+/// Inventory rows for `GET /exports/inventory`, filtered on the category bound
+/// as `$4`.
+pub const INVENTORY_EXPORT_SELECT: &str = "SELECT i.id, i.product_id, p.name, p.category, i.stock,
+       i.price_cents, i.currency, i.net_weight, i.net_weight_unit
+     FROM priced_inventories i INNER JOIN products p ON i.product_id = p.id
+     WHERE NOT i.recalled AND NOT batch_expired(i.batch_id)
+       AND ($4 IS NULL OR p.category = $4)";
+
+/// Products for `GET /exports/products`, filtered on the category bound as
+/// `$4`.
+pub const PRODUCT_EXPORT_SELECT: &str =
+    "SELECT p.id, p.name, p.category, c.family, c.thc, c.cbd, c.total_cannabinoids
+     FROM products p
+     LEFT JOIN LATERAL (
+        SELECT * FROM cannabis WHERE cannabis.product_id = p.id
+        ORDER BY cannabis.id DESC LIMIT 1
+     ) c ON true
+     WHERE $4 IS NULL OR p.category = $4";
+
+/// Streams every row of `select` in `format`, fetching `EXPORT_BATCH_SIZE`
+/// rows at a time with keyset pagination so the table is never held in
+/// memory at once. `category` is bound as `$4`. The connection is moved in
+/// and out of the blocking pool between batches.
+pub fn stream_rows<T>(
+    conn: DbConn,
+    select: &'static str,
+    category: Option<Category>,
+    format: ExportFormat,
+) -> impl Stream<Item = Result<Bytes, ApiError>>
+where
+    T: Sortable + QueryableByName<Pg> + Serialize + Send + 'static,
+{
+    let start = Some((conn, None, true));
+    stream::try_unfold(
+        start,
+        move |state: Option<(DbConn, Option<String>, bool)>| async move {
+            let (conn, cursor, first) = match state {
+                Some(state) => state,
+                None => return Ok(None),
+            };
+            let (conn, page) = web::block(move || {
+                let params = PageParams {
+                    limit: Some(EXPORT_BATCH_SIZE),
+                    cursor,
+                    sort: None,
+                };
+                load_page_with::<T, Nullable<CategoryMapping>, _>(&conn, select, category, &params)
+                    .map(|page| (conn, page))
+            })
+            .await?;
+
+            let chunk = format.encode(page.get_data(), first)?;
+            let next = page
+                .get_next_cursor()
+                .clone()
+                .map(|cursor| (conn, Some(cursor), false));
+            Ok(Some((chunk, next)))
+        },
+    )
+}
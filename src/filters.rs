@@ -0,0 +1,296 @@
+use super::models::*;
+use super::pagination::{bad_request, Page, PageParams, DEFAULT_LIMIT, MAX_LIMIT};
+use super::schema::{cannabis, inventories, products};
+
+use diesel::dsl::not;
+use diesel::pg::PgConnection;
+use diesel::result::Error;
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+
+use serde::Deserialize;
+
+use std::env;
+
+/// Query string accepted by `GET /products`. Potency bounds are inclusive
+/// percentages; `in_stock` keeps products with at least one inventory row
+/// that has stock left. Unknown keys are rejected.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProductFilter {
+    pub category: Option<Category>,
+    pub family: Option<Family>,
+    pub thc_min: Option<f32>,
+    pub thc_max: Option<f32>,
+    pub cbd_min: Option<f32>,
+    pub cbd_max: Option<f32>,
+    pub in_stock: Option<bool>,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub sort: Option<String>,
+}
+
+impl ProductFilter {
+    pub fn page_params(&self) -> PageParams {
+        PageParams {
+            limit: self.limit,
+            cursor: self.cursor.clone(),
+            sort: self.sort.clone(),
+        }
+    }
+
+    pub fn load(&self, conn: &PgConnection) -> Result<Page<Product>, Error> {
+        let params = self.page_params();
+        let sort = params.sort::<Product>()?;
+        let cursor = params.cursor(&sort)?;
+        let limit = params.limit();
+
+        let mut query = products::table
+            .left_join(cannabis::table)
+            .left_join(inventories::table)
+            .select(products::all_columns)
+            .distinct()
+            .into_boxed();
+
+        if let Some(category) = &self.category {
+            query = query.filter(products::category.eq(category));
+        }
+        if let Some(family) = &self.family {
+            query = query.filter(cannabis::family.eq(family));
+        }
+        if let Some(thc_min) = self.thc_min {
+            query = query.filter(cannabis::thc.ge(thc_min));
+        }
+        if let Some(thc_max) = self.thc_max {
+            query = query.filter(cannabis::thc.le(thc_max));
+        }
+        if let Some(cbd_min) = self.cbd_min {
+            query = query.filter(cannabis::cbd.ge(cbd_min));
+        }
+        if let Some(cbd_max) = self.cbd_max {
+            query = query.filter(cannabis::cbd.le(cbd_max));
+        }
+        if let Some(true) = self.in_stock {
+            query = query.filter(
+                inventories::stock
+                    .gt(0)
+                    .and(inventories::recalled.eq(false))
+                    .and(not(batch_expired(inventories::batch_id))),
+            );
+        }
+
+        query = match (sort.key.name, sort.desc) {
+            ("name", false) => query.order((products::name.asc(), products::id.asc())),
+            ("name", true) => query.order((products::name.desc(), products::id.desc())),
+            (_, false) => query.order(products::id.asc()),
+            (_, true) => query.order(products::id.desc()),
+        };
+
+        if let Some(c) = cursor {
+            query = match (sort.key.name, sort.desc) {
+                ("name", false) => query.filter(
+                    products::name
+                        .gt(c.value.clone())
+                        .or(products::name.eq(c.value).and(products::id.gt(c.id))),
+                ),
+                ("name", true) => query.filter(
+                    products::name
+                        .lt(c.value.clone())
+                        .or(products::name.eq(c.value).and(products::id.lt(c.id))),
+                ),
+                (_, false) => query.filter(products::id.gt(c.id)),
+                (_, true) => query.filter(products::id.lt(c.id)),
+            };
+        }
+
+        let data = query.limit(limit + 1).load(conn)?;
+        Ok(Page::from_rows(data, limit, &sort))
+    }
+}
+
+/// Query string accepted by `GET /products/search`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SearchParams {
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+impl SearchParams {
+    pub fn load(&self, conn: &PgConnection) -> Result<Vec<ProductMatch>, Error> {
+        let q = self.q.trim();
+        if q.is_empty() {
+            return Err(bad_request("Search query `q` must not be empty."));
+        }
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+        ProductMatch::search(conn, q, limit)
+    }
+}
+
+/// Query string accepted by `GET /products/{id}/similar`. Weights default to
+/// 1 and must not all be zero; `category` defaults to the product's own so
+/// flower is never ranked against cartridges unless asked for.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimilarParams {
+    pub category: Option<Category>,
+    pub terpenes: Option<f64>,
+    pub thc: Option<f64>,
+    pub cbd: Option<f64>,
+    pub total_cannabinoids: Option<f64>,
+    pub limit: Option<i64>,
+}
+
+impl SimilarParams {
+    pub fn weights(&self) -> Result<SimilarityWeights, Error> {
+        let default = SimilarityWeights::default();
+        let weights = SimilarityWeights {
+            terpenes: self.terpenes.unwrap_or(default.terpenes),
+            thc: self.thc.unwrap_or(default.thc),
+            cbd: self.cbd.unwrap_or(default.cbd),
+            total_cannabinoids: self
+                .total_cannabinoids
+                .unwrap_or(default.total_cannabinoids),
+        };
+        let values = [
+            weights.terpenes,
+            weights.thc,
+            weights.cbd,
+            weights.total_cannabinoids,
+        ];
+        if values.iter().any(|w| !w.is_finite() || *w < 0.0) {
+            return Err(bad_request(
+                "Similarity weights must be non-negative numbers.",
+            ));
+        }
+        if values.iter().all(|w| *w == 0.0) {
+            return Err(bad_request(
+                "At least one similarity weight must be positive.",
+            ));
+        }
+        Ok(weights)
+    }
+
+    pub fn load(&self, conn: &PgConnection, prod_id: &i32) -> Result<Vec<SimilarProduct>, Error> {
+        let weights = self.weights()?;
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+        SimilarProduct::rank(conn, prod_id, weights, self.category.as_ref(), limit)
+    }
+}
+
+/// Query string accepted by `GET /products/{id}`, e.g.
+/// `?expand=cannabis,terpenes,batches,inventory&unit=oz`. `unit` only
+/// applies to expanded inventory.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExpandParams {
+    pub expand: Option<String>,
+    pub unit: Option<WeightUnit>,
+}
+
+impl ExpandParams {
+    pub fn expand(&self) -> Result<Expand, Error> {
+        let mut expand = Expand {
+            unit: self.unit,
+            ..Default::default()
+        };
+        let relations = self.expand.as_deref().unwrap_or("");
+        for relation in relations
+            .split(',')
+            .map(str::trim)
+            .filter(|r| !r.is_empty())
+        {
+            match relation {
+                "cannabis" => expand.cannabis = true,
+                "terpenes" => expand.terpenes = true,
+                "batches" => expand.batches = true,
+                "inventory" => expand.inventory = true,
+                other => return Err(bad_request(&format!("Cannot expand `{}`.", other))),
+            }
+        }
+        Ok(expand)
+    }
+}
+
+/// Query string accepted by inventory reads, e.g. `?unit=oz` to report every
+/// net weight in ounces. Other keys are left to the endpoint's own params.
+#[derive(Debug, Default, Deserialize)]
+pub struct UnitParams {
+    pub unit: Option<WeightUnit>,
+}
+
+pub const DEFAULT_EXPIRING_WITHIN_DAYS: i32 = 30;
+pub const MAX_EXPIRING_WITHIN_DAYS: i32 = 3650;
+
+/// Query string accepted by `GET /inventories/expiring`. `within` is a number
+/// of days, written `30d` or just `30`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExpiringParams {
+    pub within: Option<String>,
+}
+
+impl ExpiringParams {
+    pub fn days(&self) -> Result<i32, Error> {
+        let within = match &self.within {
+            Some(within) => within.trim(),
+            None => return Ok(DEFAULT_EXPIRING_WITHIN_DAYS),
+        };
+        within
+            .strip_suffix('d')
+            .unwrap_or(within)
+            .parse::<i32>()
+            .ok()
+            .filter(|days| (0..=MAX_EXPIRING_WITHIN_DAYS).contains(days))
+            .ok_or_else(|| {
+                bad_request(&format!(
+                    "`within` must be a number of days up to {}, e.g. `30d`.",
+                    MAX_EXPIRING_WITHIN_DAYS
+                ))
+            })
+    }
+}
+
+/// THC tolerance, as a percentage of the label value, used when neither the
+/// request nor `COA_THC_TOLERANCE` sets one.
+pub const DEFAULT_THC_TOLERANCE: f32 = 10.0;
+
+/// Query string accepted by `GET /lab-results/{id}/potency`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PotencyParams {
+    pub tolerance: Option<f32>,
+}
+
+impl PotencyParams {
+    pub fn tolerance(&self) -> Result<f32, Error> {
+        let tolerance = self
+            .tolerance
+            .or_else(|| env::var("COA_THC_TOLERANCE").ok()?.parse().ok())
+            .unwrap_or(DEFAULT_THC_TOLERANCE);
+        match tolerance.is_finite() && (0.0..=100.0).contains(&tolerance) {
+            true => Ok(tolerance),
+            false => Err(bad_request(
+                "`tolerance` must be a percentage between 0 and 100.",
+            )),
+        }
+    }
+}
+
+pub const DEFAULT_LINEAGE_DEPTH: i32 = 3;
+pub const MAX_LINEAGE_DEPTH: i32 = 10;
+
+/// Query string accepted by `GET /products/cannabis/{id}/lineage`. `depth`
+/// counts generations and is clamped to `1..=MAX_LINEAGE_DEPTH`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LineageParams {
+    pub depth: Option<i32>,
+}
+
+impl LineageParams {
+    pub fn depth(&self) -> i32 {
+        self.depth
+            .unwrap_or(DEFAULT_LINEAGE_DEPTH)
+            .clamp(1, MAX_LINEAGE_DEPTH)
+    }
+}
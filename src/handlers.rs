@@ -1,120 +1,857 @@
+use super::errors::{delete_error, ApiError};
+use super::export::{stream_rows, ExportParams, INVENTORY_EXPORT_SELECT, PRODUCT_EXPORT_SELECT};
+use super::filters::{
+    ExpandParams, ExpiringParams, LineageParams, PotencyParams, ProductFilter, SearchParams,
+    SimilarParams, UnitParams,
+};
+use super::imports::{import_products, ImportParams, MAX_IMPORT_BYTES};
 use super::models::*;
-use super::{Creatable, DbPool, Readable};
+use super::pagination::PageParams;
+use super::payload::FormOrJson;
+use super::pool::DbConn;
+use super::storage;
+use super::{Cleanable, Creatable, Deletable, Readable, Updatable};
 
-use actix_web::{get, post, web, HttpResponse, Result};
+use actix_web::http::{header, StatusCode};
+use actix_web::{
+    delete, get, patch, post, put, web, HttpMessage, HttpRequest, HttpResponse, Result,
+};
+
+use futures::StreamExt;
 
 use serde_json::json;
 
+use std::fs;
+
 #[post("/products")]
 pub async fn post_product(
-    pool: web::Data<DbPool>,
-    form: web::Form<NewProduct>,
-) -> Result<HttpResponse, HttpResponse> {
-    let conn = pool.get().expect("Could not get connection from pool.");
-    web::block(move || form.into_inner().create(&conn))
+    conn: DbConn,
+    body: FormOrJson<NewProduct>,
+) -> Result<HttpResponse, ApiError> {
+    let new = body.into_inner().clean()?;
+
+    web::block(move || new.create(&conn))
         .await
         .map(|prod| HttpResponse::Ok().json(json!({"status": 200, "data": prod})))
-        .map_err(|e| {
-            HttpResponse::InternalServerError()
-                .json(json!({"status": 404, "message": e.to_string()}))
-        })
+        .map_err(ApiError::from)
 }
 
 #[get("/products")]
-pub async fn get_products(pool: web::Data<DbPool>) -> Result<HttpResponse, HttpResponse> {
-    let conn = pool.get().expect("Could not get connection from pool.");
-    web::block(move || Product::all(&conn))
+pub async fn get_products(
+    conn: DbConn,
+    query: web::Query<ProductFilter>,
+) -> Result<HttpResponse, ApiError> {
+    web::block(move || query.load(&conn))
         .await
-        .map(|prods| HttpResponse::Ok().json(json!({"status": 200, "data": prods})))
-        .map_err(|e| {
-            HttpResponse::InternalServerError()
-                .json(json!({"status": 404, "message": e.to_string()}))
+        .map(|page| {
+            HttpResponse::Ok().json(json!({
+                "status": 200,
+                "data": page.get_data(),
+                "next_cursor": page.get_next_cursor(),
+            }))
         })
+        .map_err(ApiError::from)
+}
+
+#[get("/products/search")]
+pub async fn search_products(
+    conn: DbConn,
+    query: web::Query<SearchParams>,
+) -> Result<HttpResponse, ApiError> {
+    web::block(move || query.load(&conn))
+        .await
+        .map(|prods| HttpResponse::Ok().json(json!({"status": 200, "data": prods})))
+        .map_err(ApiError::from)
 }
 
 #[get("/products/{id}")]
 pub async fn get_product_id(
-    pool: web::Data<DbPool>,
+    conn: DbConn,
+    path: web::Path<i32>,
+    query: web::Query<ExpandParams>,
+) -> Result<HttpResponse, ApiError> {
+    web::block(move || {
+        query
+            .expand()
+            .and_then(|expand| ProductDetail::load(&conn, &path.into_inner(), &expand))
+    })
+    .await
+    .map(|prod| HttpResponse::Ok().json(json!({"status": 200, "data": prod})))
+    .map_err(ApiError::from)
+}
+
+#[get("/products/{id}/similar")]
+pub async fn get_similar_products(
+    conn: DbConn,
     path: web::Path<i32>,
-) -> Result<HttpResponse, HttpResponse> {
-    let conn = pool.get().expect("Could not get connection from pool.");
-    web::block(move || Product::with_id(&conn, &path.into_inner()))
+    query: web::Query<SimilarParams>,
+) -> Result<HttpResponse, ApiError> {
+    web::block(move || query.load(&conn, &path.into_inner()))
+        .await
+        .map(|prods| HttpResponse::Ok().json(json!({"status": 200, "data": prods})))
+        .map_err(ApiError::from)
+}
+
+#[put("/products/{id}")]
+pub async fn put_product(
+    conn: DbConn,
+    path: web::Path<i32>,
+    body: FormOrJson<NewProduct>,
+) -> Result<HttpResponse, ApiError> {
+    let changes = body.into_inner().clean()?;
+
+    web::block(move || changes.update(&conn, &path.into_inner()))
         .await
         .map(|prod| HttpResponse::Ok().json(json!({"status": 200, "data": prod})))
-        .map_err(|e| {
-            HttpResponse::InternalServerError()
-                .json(json!({"status": 404, "message": e.to_string()}))
-        })
+        .map_err(ApiError::from)
+}
+
+#[patch("/products/{id}")]
+pub async fn patch_product(
+    conn: DbConn,
+    path: web::Path<i32>,
+    body: FormOrJson<ProductChanges>,
+) -> Result<HttpResponse, ApiError> {
+    let changes = body.into_inner().clean()?;
+
+    web::block(move || changes.update(&conn, &path.into_inner()))
+        .await
+        .map(|prod| HttpResponse::Ok().json(json!({"status": 200, "data": prod})))
+        .map_err(ApiError::from)
+}
+
+#[delete("/products/{id}")]
+pub async fn delete_product(conn: DbConn, path: web::Path<i32>) -> Result<HttpResponse, ApiError> {
+    let prod_id = path.into_inner();
+    web::block(move || -> Result<_, ApiError> {
+        let prod = Product::with_id(&conn, &prod_id)?;
+        let (deleted, coas) =
+            LabResult::delete_with_coas(&conn, CoaOwner::Product(prod_id), || {
+                prod.delete_cascade(&conn).map_err(delete_error)
+            })?;
+        storage::discard_coas(coas)?;
+        Ok(deleted)
+    })
+    .await
+    .map(|prod| HttpResponse::Ok().json(json!({"status": 200, "data": prod})))
+    .map_err(ApiError::from)
 }
 
 #[post("/products/cannabis")]
 pub async fn post_cannabis(
-    pool: web::Data<DbPool>,
-    form: web::Form<NewCannabis>,
-) -> Result<HttpResponse, HttpResponse> {
-    let conn = pool.get().expect("Could not get connection from pool.");
-    web::block(move || form.into_inner().create(&conn))
+    conn: DbConn,
+    body: FormOrJson<NewCannabis>,
+) -> Result<HttpResponse, ApiError> {
+    let new = body.into_inner().clean()?;
+
+    web::block(move || new.create(&conn))
         .await
         .map(|cnbs| HttpResponse::Ok().json(json!({"status": 200, "data": cnbs})))
-        .map_err(|e| {
-            HttpResponse::InternalServerError()
-                .json(json!({"status": 404, "message": e.to_string()}))
-        })
+        .map_err(ApiError::from)
 }
 
 #[get("/products/cannabis/{id}")]
-pub async fn get_cannabis_id(
-    pool: web::Data<DbPool>,
-    path: web::Path<i32>,
-) -> Result<HttpResponse, HttpResponse> {
-    let conn = pool.get().expect("Could not get connection from pool.");
+pub async fn get_cannabis_id(conn: DbConn, path: web::Path<i32>) -> Result<HttpResponse, ApiError> {
     web::block(move || Cannabis::with_id(&conn, &path.into_inner()))
         .await
         .map(|cnbs| HttpResponse::Ok().json(json!({"status": 200, "data": cnbs})))
-        .map_err(|e| {
-            HttpResponse::InternalServerError()
-                .json(json!({"status": 404, "message": e.to_string()}))
-        })
+        .map_err(ApiError::from)
+}
+
+#[put("/products/cannabis/{id}")]
+pub async fn put_cannabis(
+    conn: DbConn,
+    path: web::Path<i32>,
+    body: FormOrJson<NewCannabis>,
+) -> Result<HttpResponse, ApiError> {
+    let changes = body.into_inner().clean()?;
+
+    web::block(move || changes.update(&conn, &path.into_inner()))
+        .await
+        .map(|cnbs| HttpResponse::Ok().json(json!({"status": 200, "data": cnbs})))
+        .map_err(ApiError::from)
+}
+
+#[patch("/products/cannabis/{id}")]
+pub async fn patch_cannabis(
+    conn: DbConn,
+    path: web::Path<i32>,
+    body: FormOrJson<CannabisChanges>,
+) -> Result<HttpResponse, ApiError> {
+    let changes = body.into_inner().clean()?;
+
+    web::block(move || changes.update(&conn, &path.into_inner()))
+        .await
+        .map(|cnbs| HttpResponse::Ok().json(json!({"status": 200, "data": cnbs})))
+        .map_err(ApiError::from)
+}
+
+#[delete("/products/cannabis/{id}")]
+pub async fn delete_cannabis(conn: DbConn, path: web::Path<i32>) -> Result<HttpResponse, ApiError> {
+    let cnbs_id = path.into_inner();
+    web::block(move || -> Result<_, ApiError> {
+        let cnbs = Cannabis::with_id(&conn, &cnbs_id)?;
+        let (deleted, coas) =
+            LabResult::delete_with_coas(&conn, CoaOwner::Cannabis(cnbs_id), || cnbs.delete(&conn))?;
+        storage::discard_coas(coas)?;
+        Ok(deleted)
+    })
+    .await
+    .map(|cnbs| HttpResponse::Ok().json(json!({"status": 200, "data": cnbs})))
+    .map_err(ApiError::from)
+}
+
+#[post("/products/cannabis/{id}/terpenes")]
+pub async fn post_cannabis_terpenes(
+    conn: DbConn,
+    path: web::Path<i32>,
+    body: FormOrJson<TerpeneInput>,
+) -> Result<HttpResponse, ApiError> {
+    let new = body
+        .into_inner()
+        .clean()?
+        .into_new_terpene(path.into_inner());
+
+    web::block(move || new.create(&conn))
+        .await
+        .map(|terp| HttpResponse::Ok().json(json!({"status": 200, "data": terp})))
+        .map_err(ApiError::from)
+}
+
+#[get("/products/cannabis/{id}/terpenes")]
+pub async fn get_cannabis_terpenes(
+    conn: DbConn,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    web::block(move || Terpene::with_cannabis_id(&conn, &path.into_inner()))
+        .await
+        .map(|terps| HttpResponse::Ok().json(json!({"status": 200, "data": terps})))
+        .map_err(ApiError::from)
+}
+
+#[put("/products/cannabis/{id}/parents")]
+pub async fn put_cannabis_parents(
+    conn: DbConn,
+    path: web::Path<i32>,
+    body: FormOrJson<ParentsInput>,
+) -> Result<HttpResponse, ApiError> {
+    let input = body.into_inner().clean()?;
+
+    web::block(move || Cannabis::set_parents(&conn, &path.into_inner(), input))
+        .await
+        .map(|parents| HttpResponse::Ok().json(json!({"status": 200, "data": parents})))
+        .map_err(ApiError::from)
+}
+
+#[get("/products/cannabis/{id}/lineage")]
+pub async fn get_cannabis_lineage(
+    conn: DbConn,
+    path: web::Path<i32>,
+    query: web::Query<LineageParams>,
+) -> Result<HttpResponse, ApiError> {
+    web::block(move || Cannabis::lineage(&conn, &path.into_inner(), query.depth()))
+        .await
+        .map(|tree| HttpResponse::Ok().json(json!({"status": 200, "data": tree})))
+        .map_err(ApiError::from)
+}
+
+#[get("/products/cannabis/{id}/relatives")]
+pub async fn get_cannabis_relatives(
+    conn: DbConn,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let cnbs_id = path.into_inner();
+    web::block(move || {
+        Cannabis::with_id(&conn, &cnbs_id).and_then(|_| Cannabis::relatives(&conn, &cnbs_id))
+    })
+    .await
+    .map(|relatives| HttpResponse::Ok().json(json!({"status": 200, "data": relatives})))
+    .map_err(ApiError::from)
+}
+
+#[delete("/terpenes/{id}")]
+pub async fn delete_terpene(conn: DbConn, path: web::Path<i32>) -> Result<HttpResponse, ApiError> {
+    web::block(move || {
+        Terpene::with_id(&conn, &path.into_inner()).and_then(|terp| terp.delete(&conn))
+    })
+    .await
+    .map(|terp| HttpResponse::Ok().json(json!({"status": 200, "data": terp})))
+    .map_err(ApiError::from)
+}
+
+#[post("/products/cannabis/{id}/batches")]
+pub async fn post_cannabis_batch(
+    conn: DbConn,
+    path: web::Path<i32>,
+    body: FormOrJson<BatchInput>,
+) -> Result<HttpResponse, ApiError> {
+    let new = body.into_inner().clean()?.into_new_batch(path.into_inner());
+
+    web::block(move || new.create(&conn))
+        .await
+        .map(|batch| HttpResponse::Ok().json(json!({"status": 200, "data": batch})))
+        .map_err(ApiError::from)
+}
+
+#[get("/products/cannabis/{id}/batches")]
+pub async fn get_cannabis_batches(
+    conn: DbConn,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    web::block(move || Batch::with_cannabis_id(&conn, &path.into_inner()))
+        .await
+        .map(|batches| HttpResponse::Ok().json(json!({"status": 200, "data": batches})))
+        .map_err(ApiError::from)
+}
+
+#[get("/products/cannabis/{id}/batches/{batch_id}")]
+pub async fn get_cannabis_batch(
+    conn: DbConn,
+    path: web::Path<(i32, i32)>,
+) -> Result<HttpResponse, ApiError> {
+    let (cnbs_id, batch_id) = path.into_inner();
+    web::block(move || Batch::of_cannabis(&conn, &cnbs_id, &batch_id))
+        .await
+        .map(|batch| HttpResponse::Ok().json(json!({"status": 200, "data": batch})))
+        .map_err(ApiError::from)
+}
+
+#[put("/products/cannabis/{id}/batches/{batch_id}")]
+pub async fn put_cannabis_batch(
+    conn: DbConn,
+    path: web::Path<(i32, i32)>,
+    body: FormOrJson<BatchInput>,
+) -> Result<HttpResponse, ApiError> {
+    let (cnbs_id, batch_id) = path.into_inner();
+    let new = body.into_inner().clean()?.into_new_batch(cnbs_id);
+
+    web::block(move || {
+        Batch::of_cannabis(&conn, &cnbs_id, &batch_id).and_then(|_| new.update(&conn, &batch_id))
+    })
+    .await
+    .map(|batch| HttpResponse::Ok().json(json!({"status": 200, "data": batch})))
+    .map_err(ApiError::from)
+}
+
+#[delete("/products/cannabis/{id}/batches/{batch_id}")]
+pub async fn delete_cannabis_batch(
+    conn: DbConn,
+    path: web::Path<(i32, i32)>,
+) -> Result<HttpResponse, ApiError> {
+    let (cnbs_id, batch_id) = path.into_inner();
+    web::block(move || -> Result<_, ApiError> {
+        let batch = Batch::of_cannabis(&conn, &cnbs_id, &batch_id)?;
+        let (deleted, coas) =
+            LabResult::delete_with_coas(&conn, CoaOwner::Batch(batch_id), || batch.delete(&conn))?;
+        storage::discard_coas(coas)?;
+        Ok(deleted)
+    })
+    .await
+    .map(|batch| HttpResponse::Ok().json(json!({"status": 200, "data": batch})))
+    .map_err(ApiError::from)
+}
+
+#[get("/batches/{id}/expiration")]
+pub async fn get_batch_expiration(
+    conn: DbConn,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    web::block(move || Batch::expiration(&conn, &path.into_inner()))
+        .await
+        .map(|expiration| HttpResponse::Ok().json(json!({"status": 200, "data": expiration})))
+        .map_err(ApiError::from)
+}
+
+#[get("/shelf-lives")]
+pub async fn get_shelf_lives(conn: DbConn) -> Result<HttpResponse, ApiError> {
+    web::block(move || ShelfLife::list(&conn))
+        .await
+        .map(|lives| HttpResponse::Ok().json(json!({"status": 200, "data": lives})))
+        .map_err(ApiError::from)
+}
+
+#[put("/shelf-lives/{category}")]
+pub async fn put_shelf_life(
+    conn: DbConn,
+    path: web::Path<Category>,
+    body: FormOrJson<ShelfLifeInput>,
+) -> Result<HttpResponse, ApiError> {
+    let input = body.into_inner().clean()?;
+
+    web::block(move || ShelfLife::set(&conn, path.into_inner(), input))
+        .await
+        .map(|life| HttpResponse::Ok().json(json!({"status": 200, "data": life})))
+        .map_err(ApiError::from)
+}
+
+#[delete("/shelf-lives/{category}")]
+pub async fn delete_shelf_life(
+    conn: DbConn,
+    path: web::Path<Category>,
+) -> Result<HttpResponse, ApiError> {
+    web::block(move || ShelfLife::clear(&conn, &path.into_inner()))
+        .await
+        .map(|life| HttpResponse::Ok().json(json!({"status": 200, "data": life})))
+        .map_err(ApiError::from)
+}
+
+#[post("/batches/{id}/recall")]
+pub async fn post_batch_recall(
+    conn: DbConn,
+    path: web::Path<i32>,
+    body: FormOrJson<RecallInput>,
+) -> Result<HttpResponse, ApiError> {
+    let input = body.into_inner().clean()?;
+
+    web::block(move || Batch::recall(&conn, &path.into_inner(), input))
+        .await
+        .map(|report| HttpResponse::Ok().json(json!({"status": 200, "data": report})))
+        .map_err(ApiError::from)
+}
+
+/// The recall report of a batch, available before a recall too so the
+/// exposure can be sized up first.
+#[get("/batches/{id}/recall")]
+pub async fn get_batch_recall(
+    conn: DbConn,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    web::block(move || {
+        Batch::with_id(&conn, &path.into_inner()).and_then(|b| RecallReport::of_batch(&conn, b))
+    })
+    .await
+    .map(|report| HttpResponse::Ok().json(json!({"status": 200, "data": report})))
+    .map_err(ApiError::from)
+}
+
+#[post("/batches/{id}/lab-results")]
+pub async fn post_batch_lab_result(
+    conn: DbConn,
+    path: web::Path<i32>,
+    body: FormOrJson<LabResultInput>,
+) -> Result<HttpResponse, ApiError> {
+    let input = body.into_inner().clean()?;
+
+    web::block(move || LabResult::record(&conn, &path.into_inner(), input))
+        .await
+        .map(|result| HttpResponse::Ok().json(json!({"status": 200, "data": result})))
+        .map_err(ApiError::from)
+}
+
+#[get("/batches/{id}/lab-results")]
+pub async fn get_batch_lab_results(
+    conn: DbConn,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    web::block(move || LabResult::with_batch_id(&conn, &path.into_inner()))
+        .await
+        .map(|results| HttpResponse::Ok().json(json!({"status": 200, "data": results})))
+        .map_err(ApiError::from)
+}
+
+#[get("/lab-results/{id}")]
+pub async fn get_lab_result(conn: DbConn, path: web::Path<i32>) -> Result<HttpResponse, ApiError> {
+    web::block(move || LabResult::with_id(&conn, &path.into_inner()))
+        .await
+        .map(|result| HttpResponse::Ok().json(json!({"status": 200, "data": result})))
+        .map_err(ApiError::from)
+}
+
+#[get("/lab-results/{id}/potency")]
+pub async fn get_lab_result_potency(
+    conn: DbConn,
+    path: web::Path<i32>,
+    query: web::Query<PotencyParams>,
+) -> Result<HttpResponse, ApiError> {
+    let tolerance = query.tolerance()?;
+    web::block(move || {
+        LabResult::with_id(&conn, &path.into_inner())
+            .and_then(|result| result.potency_check(&conn, tolerance))
+    })
+    .await
+    .map(|check| HttpResponse::Ok().json(json!({"status": 200, "data": check})))
+    .map_err(ApiError::from)
+}
+
+/// Stores the original COA document, sent as the raw request body. A new
+/// upload replaces the previous document.
+#[put("/lab-results/{id}/coa")]
+pub async fn put_lab_result_coa(
+    conn: DbConn,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    mut body: web::Payload,
+) -> Result<HttpResponse, ApiError> {
+    let content_type = req.content_type().to_owned();
+    let extension = storage::coa_extension(&content_type).ok_or_else(|| {
+        ApiError::UnsupportedMediaType(format!(
+            "Unsupported content type `{}`; a COA must be a PDF, PNG or JPEG.",
+            content_type
+        ))
+    })?;
+    let mut bytes = web::BytesMut::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| ApiError::BadRequest(e.to_string()))?;
+        if bytes.len() + chunk.len() > storage::MAX_COA_BYTES {
+            return Err(ApiError::PayloadTooLarge(format!(
+                "A COA may be at most {} bytes.",
+                storage::MAX_COA_BYTES
+            )));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    if bytes.is_empty() {
+        return Err(ApiError::BadRequest("COA document is empty.".to_owned()));
+    }
+
+    let _id = path.into_inner();
+    web::block(move || -> Result<LabResult, ApiError> {
+        LabResult::with_id(&conn, &_id)?;
+        let saved = storage::save_coa(_id, extension, &bytes)?;
+        match LabResult::attach_coa(&conn, &_id, &saved.to_string_lossy(), &content_type) {
+            Ok((after, replaced)) => {
+                storage::discard_coas(replaced)?;
+                Ok(after)
+            }
+            Err(e) => {
+                storage::remove_coa(&saved)?;
+                Err(e.into())
+            }
+        }
+    })
+    .await
+    .map(|result| HttpResponse::Ok().json(json!({"status": 200, "data": result})))
+    .map_err(ApiError::from)
+}
+
+#[get("/lab-results/{id}/coa")]
+pub async fn get_lab_result_coa(
+    conn: DbConn,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let _id = path.into_inner();
+    let (content_type, document) = web::block(move || -> Result<_, ApiError> {
+        let result = LabResult::with_id(&conn, &_id)?;
+        match (result.get_coa_path(), result.get_coa_content_type()) {
+            (Some(path), Some(content_type)) => Ok((content_type.to_owned(), fs::read(path)?)),
+            _ => Err(ApiError::NotFound(format!(
+                "Lab result {} has no COA document.",
+                _id
+            ))),
+        }
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().content_type(content_type).body(document))
+}
+
+#[delete("/lab-results/{id}")]
+pub async fn delete_lab_result(
+    conn: DbConn,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let _id = path.into_inner();
+    web::block(move || -> Result<_, ApiError> {
+        let deleted = LabResult::with_id(&conn, &_id)?.delete(&conn)?;
+        storage::discard_coas(deleted.get_coa_path().map(str::to_owned))?;
+        Ok(deleted)
+    })
+    .await
+    .map(|result| HttpResponse::Ok().json(json!({"status": 200, "data": result})))
+    .map_err(ApiError::from)
 }
 
 #[post("/inventories")]
 pub async fn post_inventory(
-    pool: web::Data<DbPool>,
-    form: web::Form<NewInventory>,
-) -> Result<HttpResponse, HttpResponse> {
-    let conn = pool.get().expect("Could not get connection from pool.");
-    web::block(move || form.into_inner().create(&conn))
+    conn: DbConn,
+    body: FormOrJson<NewInventory>,
+) -> Result<HttpResponse, ApiError> {
+    let new = body.into_inner().clean()?;
+
+    web::block(move || new.create(&conn))
         .await
         .map(|inv| HttpResponse::Ok().json(json!({"status": 200, "data": inv})))
-        .map_err(|e| {
-            HttpResponse::InternalServerError()
-                .json(json!({"status": 404, "message": e.to_string()}))
-        })
+        .map_err(ApiError::from)
 }
 
 #[get("/products/{id}/inventory")]
 pub async fn get_product_inventory(
-    pool: web::Data<DbPool>,
+    conn: DbConn,
+    path: web::Path<i32>,
+    query: web::Query<UnitParams>,
+) -> Result<HttpResponse, ApiError> {
+    let unit = query.unit;
+    web::block(move || {
+        Inventory::with_product_id(&conn, &path.into_inner())
+            .map(|inv| inv.into_iter().map(|i| i.in_unit(unit)).collect::<Vec<_>>())
+    })
+    .await
+    .map(|inv| HttpResponse::Ok().json(json!({"status": 200, "data": inv})))
+    .map_err(ApiError::from)
+}
+
+#[get("/inventories")]
+pub async fn get_inventories(
+    conn: DbConn,
+    query: web::Query<PageParams>,
+    units: web::Query<UnitParams>,
+) -> Result<HttpResponse, ApiError> {
+    let unit = units.unit;
+    web::block(move || {
+        InventoryResponse::page(&conn, &query).map(|page| page.map(|i| i.in_unit(unit)))
+    })
+    .await
+    .map(|page| {
+        HttpResponse::Ok().json(json!({
+            "status": 200,
+            "data": page.get_data(),
+            "next_cursor": page.get_next_cursor(),
+        }))
+    })
+    .map_err(ApiError::from)
+}
+
+#[get("/inventories/low-stock")]
+pub async fn get_low_stock(conn: DbConn) -> Result<HttpResponse, ApiError> {
+    web::block(move || LowStockItem::report(&conn))
+        .await
+        .map(|items| HttpResponse::Ok().json(json!({"status": 200, "data": items})))
+        .map_err(ApiError::from)
+}
+
+#[get("/inventories/expiring")]
+pub async fn get_expiring_inventories(
+    conn: DbConn,
+    query: web::Query<ExpiringParams>,
+) -> Result<HttpResponse, ApiError> {
+    let days = query.days()?;
+    web::block(move || ExpiringInventory::within(&conn, days))
+        .await
+        .map(|items| HttpResponse::Ok().json(json!({"status": 200, "data": items})))
+        .map_err(ApiError::from)
+}
+
+#[put("/inventories/{id}")]
+pub async fn put_inventory(
+    conn: DbConn,
     path: web::Path<i32>,
-) -> Result<HttpResponse, HttpResponse> {
-    let conn = pool.get().expect("Could not get connection from pool.");
-    web::block(move || Inventory::with_product_id(&conn, &path.into_inner()))
+    body: FormOrJson<InventoryReplacement>,
+) -> Result<HttpResponse, ApiError> {
+    let changes = body.into_inner().clean()?;
+
+    web::block(move || changes.update(&conn, &path.into_inner()))
         .await
         .map(|inv| HttpResponse::Ok().json(json!({"status": 200, "data": inv})))
-        .map_err(|e| {
-            HttpResponse::InternalServerError()
-                .json(json!({"status": 404, "message": e.to_string()}))
-        })
+        .map_err(ApiError::from)
 }
 
-#[get("/inventories")]
-pub async fn get_inventories(pool: web::Data<DbPool>) -> Result<HttpResponse, HttpResponse> {
-    let conn = pool.get().expect("Could not get connection from pool.");
-    web::block(move || InventoryResponse::all(&conn))
+#[patch("/inventories/{id}")]
+pub async fn patch_inventory(
+    conn: DbConn,
+    path: web::Path<i32>,
+    body: FormOrJson<InventoryChanges>,
+) -> Result<HttpResponse, ApiError> {
+    let changes = body.into_inner().clean()?;
+
+    web::block(move || changes.update(&conn, &path.into_inner()))
         .await
         .map(|inv| HttpResponse::Ok().json(json!({"status": 200, "data": inv})))
-        .map_err(|e| {
-            HttpResponse::InternalServerError()
-                .json(json!({"status": 404, "message": e.to_string()}))
+        .map_err(ApiError::from)
+}
+
+#[delete("/inventories/{id}")]
+pub async fn delete_inventory(
+    conn: DbConn,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    web::block(move || {
+        Inventory::with_id(&conn, &path.into_inner())
+            .and_then(|inv| inv.delete(&conn))
+            .map_err(delete_error)
+    })
+    .await
+    .map(|inv| HttpResponse::Ok().json(json!({"status": 200, "data": inv})))
+    .map_err(ApiError::from)
+}
+
+#[post("/inventories/{id}/movements")]
+pub async fn post_inventory_movement(
+    conn: DbConn,
+    path: web::Path<i32>,
+    body: FormOrJson<MovementInput>,
+) -> Result<HttpResponse, ApiError> {
+    let input = body.into_inner().clean()?;
+
+    web::block(move || StockMovement::record(&conn, &path.into_inner(), input))
+        .await
+        .map(|mvmt| HttpResponse::Ok().json(json!({"status": 200, "data": mvmt})))
+        .map_err(ApiError::from)
+}
+
+#[get("/inventories/{id}/movements")]
+pub async fn get_inventory_movements(
+    conn: DbConn,
+    path: web::Path<i32>,
+    query: web::Query<PageParams>,
+) -> Result<HttpResponse, ApiError> {
+    let inv_id = path.into_inner();
+    web::block(move || {
+        Inventory::with_id(&conn, &inv_id)
+            .and_then(|_| StockMovement::of_inventory(&conn, &inv_id, &query))
+    })
+    .await
+    .map(|page| {
+        HttpResponse::Ok().json(json!({
+            "status": 200,
+            "data": page.get_data(),
+            "next_cursor": page.get_next_cursor(),
+        }))
+    })
+    .map_err(ApiError::from)
+}
+
+#[post("/inventories/{id}/prices")]
+pub async fn post_inventory_price(
+    conn: DbConn,
+    path: web::Path<i32>,
+    body: FormOrJson<PriceInput>,
+) -> Result<HttpResponse, ApiError> {
+    let input = body.into_inner().clean()?;
+
+    web::block(move || PriceChange::schedule(&conn, &path.into_inner(), input))
+        .await
+        .map(|price| HttpResponse::Ok().json(json!({"status": 200, "data": price})))
+        .map_err(ApiError::from)
+}
+
+#[get("/inventories/{id}/prices")]
+pub async fn get_inventory_prices(
+    conn: DbConn,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let inv_id = path.into_inner();
+    web::block(move || {
+        // every item has a price row from the moment it is created, so an
+        // empty timeline means the id never existed
+        let prices = PriceChange::timeline(&conn, &inv_id)?;
+        match prices.is_empty() {
+            true => Inventory::with_id(&conn, &inv_id).map(|_| prices),
+            false => Ok(prices),
+        }
+    })
+    .await
+    .map(|prices| HttpResponse::Ok().json(json!({"status": 200, "data": prices})))
+    .map_err(ApiError::from)
+}
+
+#[post("/checkout")]
+pub async fn post_checkout(
+    conn: DbConn,
+    body: FormOrJson<CheckoutInput>,
+) -> Result<HttpResponse, ApiError> {
+    let input = body.into_inner().clean()?;
+
+    web::block(move || Order::checkout(&conn, input))
+        .await
+        .map(|order| HttpResponse::Ok().json(json!({"status": 200, "data": order})))
+        .map_err(ApiError::from)
+}
+
+#[get("/orders")]
+pub async fn get_orders(
+    conn: DbConn,
+    query: web::Query<PageParams>,
+) -> Result<HttpResponse, ApiError> {
+    web::block(move || Order::page(&conn, &query))
+        .await
+        .map(|page| {
+            HttpResponse::Ok().json(json!({
+                "status": 200,
+                "data": page.get_data(),
+                "next_cursor": page.get_next_cursor(),
+            }))
         })
+        .map_err(ApiError::from)
+}
+
+#[get("/orders/{id}")]
+pub async fn get_order(conn: DbConn, path: web::Path<i32>) -> Result<HttpResponse, ApiError> {
+    web::block(move || Order::detail(&conn, &path.into_inner()))
+        .await
+        .map(|order| HttpResponse::Ok().json(json!({"status": 200, "data": order})))
+        .map_err(ApiError::from)
+}
+
+#[patch("/orders/{id}")]
+pub async fn patch_order(
+    conn: DbConn,
+    path: web::Path<i32>,
+    body: FormOrJson<OrderStatusInput>,
+) -> Result<HttpResponse, ApiError> {
+    let input = body.into_inner();
+
+    web::block(move || Order::transition(&conn, &path.into_inner(), input))
+        .await
+        .map(|order| HttpResponse::Ok().json(json!({"status": 200, "data": order})))
+        .map_err(ApiError::from)
+}
+
+/// `POST /imports/products`. Registered in `main` as a resource of its own so
+/// that its `PayloadConfig` raises the body limit for this route only.
+pub async fn post_products_import(
+    conn: DbConn,
+    query: web::Query<ImportParams>,
+    body: Result<web::Bytes>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.map_err(|e| match e.as_response_error().status_code() {
+        StatusCode::PAYLOAD_TOO_LARGE => ApiError::PayloadTooLarge(format!(
+            "An import may be at most {} bytes.",
+            MAX_IMPORT_BYTES
+        )),
+        _ => ApiError::BadRequest(e.to_string()),
+    })?;
+    let dry_run = query.dry_run;
+    let report = web::block(move || import_products(&conn, &body, dry_run)).await?;
+
+    match report.is_ok() {
+        true => Ok(HttpResponse::Ok().json(json!({"status": 200, "data": report}))),
+        false => Ok(HttpResponse::UnprocessableEntity().json(json!({
+            "status": 422,
+            "message": "Import failed; no rows were written.",
+            "data": report,
+        }))),
+    }
+}
+
+#[get("/exports/inventory")]
+pub async fn export_inventory(
+    conn: DbConn,
+    query: web::Query<ExportParams>,
+) -> Result<HttpResponse, ApiError> {
+    let format = query.format;
+    let rows =
+        stream_rows::<InventoryResponse>(conn, INVENTORY_EXPORT_SELECT, query.category, format);
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .set_header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"inventory.{}\"", format.extension()),
+        )
+        .streaming(Box::pin(rows)))
+}
+
+#[get("/exports/products")]
+pub async fn export_products(
+    conn: DbConn,
+    query: web::Query<ExportParams>,
+) -> Result<HttpResponse, ApiError> {
+    let format = query.format;
+    let rows = stream_rows::<ProductExport>(conn, PRODUCT_EXPORT_SELECT, query.category, format);
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .set_header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"products.{}\"", format.extension()),
+        )
+        .streaming(Box::pin(rows)))
 }
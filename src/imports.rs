@@ -0,0 +1,166 @@
+use super::models::*;
+use super::pagination::bad_request;
+use super::validation::FieldErrors;
+use super::{Cleanable, Creatable};
+
+use diesel::pg::PgConnection;
+use diesel::result::Error;
+use diesel::Connection;
+
+use serde::{Deserialize, Serialize};
+
+/// Largest spreadsheet `POST /imports/products` accepts.
+pub const MAX_IMPORT_BYTES: usize = 20 * 1024 * 1024;
+
+/// Query string accepted by `POST /imports/products`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportParams {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// One line of a vendor spreadsheet. Cannabis columns (`family`, `thc`,
+/// `cbd`, `total_cannabinoids`) and inventory columns (`stock`, `price`,
+/// `net_weight`) are each all-or-nothing, so accessories can leave the
+/// cannabis columns blank. `currency` and `net_weight_unit` are optional and
+/// default to USD and grams.
+#[derive(Debug, Deserialize)]
+pub struct ImportRow {
+    pub name: String,
+    pub category: Category,
+    pub family: Option<Family>,
+    pub thc: Option<f32>,
+    pub cbd: Option<f32>,
+    pub total_cannabinoids: Option<f32>,
+    pub stock: Option<i32>,
+    pub price: Option<String>,
+    pub currency: Option<String>,
+    pub net_weight: Option<f32>,
+    pub net_weight_unit: Option<WeightUnit>,
+}
+
+impl ImportRow {
+    fn create(self, conn: &PgConnection) -> Result<(), Error> {
+        let cannabis = match (self.family, self.thc, self.cbd, self.total_cannabinoids) {
+            (Some(family), Some(thc), Some(cbd), Some(total)) => Some((family, thc, cbd, total)),
+            (None, None, None, None) => None,
+            _ => {
+                return Err(bad_request(
+                    "`family`, `thc`, `cbd` and `total_cannabinoids` must be given together.",
+                ))
+            }
+        };
+        let inventory = match (self.stock, self.price, self.net_weight) {
+            (Some(stock), Some(price), Some(weight)) => Some((stock, price, weight)),
+            (None, None, None) => None,
+            _ => {
+                return Err(bad_request(
+                    "`stock`, `price` and `net_weight` must be given together.",
+                ))
+            }
+        };
+
+        let inventory = match inventory {
+            Some((stock, price, weight)) => {
+                let currency = self.currency.as_deref().unwrap_or(DEFAULT_CURRENCY);
+                let price = Money::parse(&price, currency).map_err(|e| bad_request(&e))?;
+                let unit = self.net_weight_unit.unwrap_or_default();
+                Some((stock, price, Weight::new(weight, unit)))
+            }
+            None => None,
+        };
+
+        let invalid = |e: FieldErrors| bad_request(&e.to_string());
+        let prod = NewProduct::new(&self.name, self.category)
+            .clean()
+            .map_err(invalid)?
+            .create(conn)?;
+        if let Some((family, thc, cbd, total)) = cannabis {
+            NewCannabis::new(*prod.get_id(), family, thc, cbd, total)
+                .clean()
+                .map_err(invalid)?
+                .create(conn)?;
+        }
+        if let Some((stock, price, weight)) = inventory {
+            NewInventory::new(*prod.get_id(), stock, price, weight)
+                .clean()
+                .map_err(invalid)?
+                .create(conn)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowError {
+    row: usize,
+    message: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    dry_run: bool,
+    rows: usize,
+    created: usize,
+    errors: Vec<RowError>,
+}
+
+impl ImportReport {
+    pub fn get_rows(&self) -> &usize {
+        &self.rows
+    }
+
+    pub fn get_created(&self) -> &usize {
+        &self.created
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Imports a CSV of products with their cannabis and inventory rows. Every row
+/// runs in its own savepoint inside one transaction, so a bad row is reported
+/// without hiding errors in later rows. The transaction is only committed if
+/// every row succeeded and this is not a dry run.
+pub fn import_products(
+    conn: &PgConnection,
+    csv: &[u8],
+    dry_run: bool,
+) -> Result<ImportReport, Error> {
+    let mut report = ImportReport {
+        dry_run,
+        ..Default::default()
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(csv);
+
+    let result = conn.transaction::<(), Error, _>(|| {
+        for record in reader.deserialize::<ImportRow>() {
+            report.rows += 1;
+            let created = match record {
+                Ok(row) => conn.transaction(|| row.create(conn)),
+                Err(e) => Err(bad_request(&e.to_string())),
+            };
+            match created {
+                Ok(()) => report.created += 1,
+                Err(e) => report.errors.push(RowError {
+                    row: report.rows,
+                    message: e.to_string(),
+                }),
+            }
+        }
+
+        match dry_run || !report.is_ok() {
+            true => Err(Error::RollbackTransaction),
+            false => Ok(()),
+        }
+    });
+
+    match result {
+        Ok(()) | Err(Error::RollbackTransaction) => Ok(report),
+        Err(e) => Err(e),
+    }
+}
@@ -1,32 +1,49 @@
 #[macro_use]
 extern crate diesel;
 
+use self::filters::ProductFilter;
 use self::models::*;
+use self::pagination::{load_page, Page, PageParams};
+use self::schema::batches::dsl::batches;
 use self::schema::cannabis::dsl::cannabis;
 use self::schema::inventories::dsl::inventories;
+use self::schema::lab_results::dsl::lab_results;
+use self::schema::orders::dsl::orders;
 use self::schema::products::dsl::{name, products};
+use self::schema::terpenes::dsl::terpenes;
+use self::validation::FieldErrors;
 
 use diesel::backend::Backend;
 use diesel::pg::{Pg, PgConnection};
-use diesel::r2d2::{self, ConnectionManager};
 use diesel::sql_types::Integer;
 use diesel::{result::Error, sql_query, Connection, ConnectionError, QueryDsl, RunQueryDsl};
 use serde::Serialize;
 
 use std::env;
 
+pub mod errors;
+pub mod export;
+pub mod filters;
 pub mod handlers;
+pub mod imports;
 mod models;
+pub mod pagination;
 mod schema;
+pub mod storage;
 mod tests;
 
+pub use self::models::Money;
+pub use common::{payload, pool, validation, DbPool};
+
 pub mod exports {
     pub use super::models::CategoryMapping as Category;
     pub use super::models::FamilyMapping as Family;
+    pub use super::models::MovementKindMapping as MovementKind;
+    pub use super::models::OrderStatusMapping as OrderStatus;
+    pub use super::models::TestOutcomeMapping as TestOutcome;
+    pub use super::models::WeightUnitMapping as WeightUnit;
 }
 
-pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
-
 pub fn establish_connection() -> Result<PgConnection, ConnectionError> {
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
     PgConnection::establish(&database_url)
@@ -54,6 +71,18 @@ where
 {
     fn all(conn: &Conn) -> Result<Vec<Obj>, Er>;
     fn with_id(conn: &Conn, _id: &i32) -> Result<Obj, Er>;
+    fn page(conn: &Conn, params: &PageParams) -> Result<Page<Obj>, Er>;
+}
+
+pub trait Updatable<Id = i32, Db = Pg, Conn = PgConnection, Er = Error>
+where
+    Id: Eq,
+    Db: Backend,
+    Conn: Connection,
+{
+    type Object;
+
+    fn update(&self, conn: &Conn, _id: &Id) -> Result<Self::Object, Er>;
 }
 
 pub trait Deletable<Id = i32, Obj = Self, Db = Pg, Conn = PgConnection, Er = Error>
@@ -65,6 +94,142 @@ where
     fn delete(&self, conn: &Conn) -> Result<Obj, Er>;
 }
 
+pub trait Cleanable<Er = FieldErrors> {
+    type Output;
+
+    fn clean(self) -> Result<Self::Output, Er>;
+}
+
+impl Cleanable for NewProduct {
+    type Output = NewProduct;
+
+    fn clean(self) -> Result<NewProduct, FieldErrors> {
+        let new = self.trimmed();
+        new.check().into_result(new)
+    }
+}
+
+impl Cleanable for ProductChanges {
+    type Output = ProductChanges;
+
+    fn clean(self) -> Result<ProductChanges, FieldErrors> {
+        let changes = self.trimmed();
+        changes.check().into_result(changes)
+    }
+}
+
+impl Cleanable for NewCannabis {
+    type Output = NewCannabis;
+
+    fn clean(self) -> Result<NewCannabis, FieldErrors> {
+        self.check().into_result(self)
+    }
+}
+
+impl Cleanable for CannabisChanges {
+    type Output = CannabisChanges;
+
+    fn clean(self) -> Result<CannabisChanges, FieldErrors> {
+        self.check().into_result(self)
+    }
+}
+
+impl Cleanable for TerpeneInput {
+    type Output = TerpeneInput;
+
+    fn clean(self) -> Result<TerpeneInput, FieldErrors> {
+        self.check().into_result(self)
+    }
+}
+
+impl Cleanable for BatchInput {
+    type Output = BatchInput;
+
+    fn clean(self) -> Result<BatchInput, FieldErrors> {
+        self.check().into_result(self)
+    }
+}
+
+impl Cleanable for NewInventory {
+    type Output = NewInventory;
+
+    fn clean(self) -> Result<NewInventory, FieldErrors> {
+        self.check().into_result(self)
+    }
+}
+
+impl Cleanable for InventoryReplacement {
+    type Output = NewInventory;
+
+    fn clean(self) -> Result<NewInventory, FieldErrors> {
+        self.check().into_result(self.0)
+    }
+}
+
+impl Cleanable for MovementInput {
+    type Output = MovementInput;
+
+    fn clean(self) -> Result<MovementInput, FieldErrors> {
+        self.check().into_result(self)
+    }
+}
+
+impl Cleanable for CheckoutInput {
+    type Output = CheckoutInput;
+
+    fn clean(self) -> Result<CheckoutInput, FieldErrors> {
+        self.check().into_result(self)
+    }
+}
+
+impl Cleanable for PriceInput {
+    type Output = PriceInput;
+
+    fn clean(self) -> Result<PriceInput, FieldErrors> {
+        self.check().into_result(self)
+    }
+}
+
+impl Cleanable for LabResultInput {
+    type Output = LabResultInput;
+
+    fn clean(self) -> Result<LabResultInput, FieldErrors> {
+        self.check().into_result(self)
+    }
+}
+
+impl Cleanable for ShelfLifeInput {
+    type Output = ShelfLifeInput;
+
+    fn clean(self) -> Result<ShelfLifeInput, FieldErrors> {
+        self.check().into_result(self)
+    }
+}
+
+impl Cleanable for RecallInput {
+    type Output = RecallInput;
+
+    fn clean(self) -> Result<RecallInput, FieldErrors> {
+        self.check().into_result(self)
+    }
+}
+
+impl Cleanable for ParentsInput {
+    type Output = ParentsInput;
+
+    fn clean(self) -> Result<ParentsInput, FieldErrors> {
+        self.check().into_result(self)
+    }
+}
+
+impl Cleanable for InventoryChanges {
+    type Output = InventoryChanges;
+
+    fn clean(self) -> Result<InventoryChanges, FieldErrors> {
+        self.check().into_result(self)
+    }
+}
+
 impl Creatable for NewProduct {
     type Object = Product;
 
@@ -85,9 +250,31 @@ impl Creatable for NewInventory {
     type Object = Inventory;
 
     fn create(&self, conn: &PgConnection) -> Result<Inventory, Error> {
-        diesel::insert_into(inventories)
-            .values(self)
-            .get_result(conn)
+        conn.transaction(|| {
+            let inv: Inventory = diesel::insert_into(inventories)
+                .values(self)
+                .get_result(conn)?;
+            inv.check_batch(conn)?;
+            StockMovement::reconcile(conn, inv.get_id(), 0, *inv.get_stock(), "Initial stock")?;
+            PriceChange::record_current(conn, &inv)?;
+            Ok(inv)
+        })
+    }
+}
+
+impl Creatable for NewTerpene {
+    type Object = Terpene;
+
+    fn create(&self, conn: &PgConnection) -> Result<Terpene, Error> {
+        diesel::insert_into(terpenes).values(self).get_result(conn)
+    }
+}
+
+impl Creatable for NewBatch {
+    type Object = Batch;
+
+    fn create(&self, conn: &PgConnection) -> Result<Batch, Error> {
+        diesel::insert_into(batches).values(self).get_result(conn)
     }
 }
 
@@ -99,6 +286,16 @@ impl Readable for Product {
     fn with_id(conn: &PgConnection, _id: &i32) -> Result<Product, Error> {
         products.find(_id).get_result(conn)
     }
+
+    fn page(conn: &PgConnection, params: &PageParams) -> Result<Page<Product>, Error> {
+        let filter = ProductFilter {
+            limit: params.limit,
+            cursor: params.cursor.clone(),
+            sort: params.sort.clone(),
+            ..Default::default()
+        };
+        filter.load(conn)
+    }
 }
 
 impl Readable for Cannabis {
@@ -109,33 +306,198 @@ impl Readable for Cannabis {
     fn with_id(conn: &PgConnection, _id: &i32) -> Result<Cannabis, Error> {
         cannabis.find(_id).get_result(conn)
     }
+
+    fn page(conn: &PgConnection, params: &PageParams) -> Result<Page<Cannabis>, Error> {
+        load_page(conn, "SELECT * FROM cannabis", params)
+    }
 }
 
 impl Readable for Inventory {
     fn all(conn: &PgConnection) -> Result<Vec<Inventory>, Error> {
-        inventories.load(conn)
+        sql_query("SELECT * FROM priced_inventories").load(conn)
     }
 
     fn with_id(conn: &PgConnection, _id: &i32) -> Result<Inventory, Error> {
-        inventories.find(_id).get_result(conn)
+        sql_query("SELECT * FROM priced_inventories WHERE id = $1")
+            .bind::<Integer, _>(_id)
+            .get_result(conn)
+    }
+
+    fn page(conn: &PgConnection, params: &PageParams) -> Result<Page<Inventory>, Error> {
+        load_page(conn, "SELECT * FROM priced_inventories", params)
+    }
+}
+
+impl Readable for Terpene {
+    fn all(conn: &PgConnection) -> Result<Vec<Terpene>, Error> {
+        terpenes.load(conn)
+    }
+
+    fn with_id(conn: &PgConnection, _id: &i32) -> Result<Terpene, Error> {
+        terpenes.find(_id).get_result(conn)
+    }
+
+    fn page(conn: &PgConnection, params: &PageParams) -> Result<Page<Terpene>, Error> {
+        load_page(conn, "SELECT * FROM terpenes", params)
+    }
+}
+
+impl Readable for Batch {
+    fn all(conn: &PgConnection) -> Result<Vec<Batch>, Error> {
+        batches.load(conn)
+    }
+
+    fn with_id(conn: &PgConnection, _id: &i32) -> Result<Batch, Error> {
+        batches.find(_id).get_result(conn)
+    }
+
+    fn page(conn: &PgConnection, params: &PageParams) -> Result<Page<Batch>, Error> {
+        load_page(conn, "SELECT * FROM batches", params)
+    }
+}
+
+impl Readable for LabResult {
+    fn all(conn: &PgConnection) -> Result<Vec<LabResult>, Error> {
+        lab_results.load(conn)
+    }
+
+    fn with_id(conn: &PgConnection, _id: &i32) -> Result<LabResult, Error> {
+        lab_results.find(_id).get_result(conn)
+    }
+
+    fn page(conn: &PgConnection, params: &PageParams) -> Result<Page<LabResult>, Error> {
+        load_page(conn, "SELECT * FROM lab_results", params)
+    }
+}
+
+impl Readable for Order {
+    fn all(conn: &PgConnection) -> Result<Vec<Order>, Error> {
+        orders.load(conn)
+    }
+
+    fn with_id(conn: &PgConnection, _id: &i32) -> Result<Order, Error> {
+        orders.find(_id).get_result(conn)
+    }
+
+    fn page(conn: &PgConnection, params: &PageParams) -> Result<Page<Order>, Error> {
+        load_page(conn, "SELECT * FROM orders", params)
     }
 }
 
 impl Readable for InventoryResponse {
     fn all(conn: &PgConnection) -> Result<Vec<InventoryResponse>, Error> {
         let _stmt = "SELECT i.id, i.product_id, p.name, p.category, i.stock,
-                      i.price, i.net_weight
-                     FROM inventories i INNER JOIN products p ON i.product_id = p.id";
+                      i.price_cents, i.currency, i.net_weight, i.net_weight_unit
+                     FROM priced_inventories i INNER JOIN products p ON i.product_id = p.id
+                     WHERE NOT i.recalled AND NOT batch_expired(i.batch_id)";
         sql_query(_stmt).load(conn)
     }
 
     fn with_id(conn: &PgConnection, _id: &i32) -> Result<InventoryResponse, Error> {
         let _stmt = "SELECT i.id, i.product_id, p.name, p.category, i.stock,
-                      i.price, i.net_weight
-                     FROM inventories i INNER JOIN products p ON i.product_id = p.id
+                      i.price_cents, i.currency, i.net_weight, i.net_weight_unit
+                     FROM priced_inventories i INNER JOIN products p ON i.product_id = p.id
                      WHERE i.id = $1";
         sql_query(_stmt).bind::<Integer, _>(_id).get_result(conn)
     }
+
+    fn page(conn: &PgConnection, params: &PageParams) -> Result<Page<InventoryResponse>, Error> {
+        let _stmt = "SELECT i.id, i.product_id, p.name, p.category, i.stock,
+                      i.price_cents, i.currency, i.net_weight, i.net_weight_unit
+                     FROM priced_inventories i INNER JOIN products p ON i.product_id = p.id
+                     WHERE NOT i.recalled AND NOT batch_expired(i.batch_id)";
+        load_page(conn, _stmt, params)
+    }
+}
+
+impl Updatable for NewProduct {
+    type Object = Product;
+
+    fn update(&self, conn: &PgConnection, _id: &i32) -> Result<Product, Error> {
+        diesel::update(products.find(_id)).set(self).get_result(conn)
+    }
+}
+
+impl Updatable for ProductChanges {
+    type Object = Product;
+
+    fn update(&self, conn: &PgConnection, _id: &i32) -> Result<Product, Error> {
+        diesel::update(products.find(_id)).set(self).get_result(conn)
+    }
+}
+
+impl Updatable for NewCannabis {
+    type Object = Cannabis;
+
+    fn update(&self, conn: &PgConnection, _id: &i32) -> Result<Cannabis, Error> {
+        diesel::update(cannabis.find(_id)).set(self).get_result(conn)
+    }
+}
+
+impl Updatable for NewInventory {
+    type Object = Inventory;
+
+    fn update(&self, conn: &PgConnection, _id: &i32) -> Result<Inventory, Error> {
+        conn.transaction(|| {
+            let before = Inventory::lock_current(conn, _id)?;
+            let inv: Inventory = diesel::update(inventories.find(_id))
+                .set(self)
+                .get_result(conn)?;
+            StockMovement::reconcile(
+                conn,
+                _id,
+                *before.get_stock(),
+                *inv.get_stock(),
+                "Stock set directly",
+            )?;
+            if (inv.get_batch_id(), inv.get_product_id())
+                != (before.get_batch_id(), before.get_product_id())
+            {
+                inv.check_batch(conn)?;
+            }
+            if before.get_price() != inv.get_price() {
+                PriceChange::record_current(conn, &inv)?;
+            }
+            Ok(inv)
+        })
+    }
+}
+
+impl Updatable for InventoryChanges {
+    type Object = Inventory;
+
+    fn update(&self, conn: &PgConnection, _id: &i32) -> Result<Inventory, Error> {
+        conn.transaction(|| {
+            let before = Inventory::lock_current(conn, _id)?;
+            let inv: Inventory = diesel::update(inventories.find(_id))
+                .set(self)
+                .get_result(conn)?;
+            StockMovement::reconcile(
+                conn,
+                _id,
+                *before.get_stock(),
+                *inv.get_stock(),
+                "Stock set directly",
+            )?;
+            if (inv.get_batch_id(), inv.get_product_id())
+                != (before.get_batch_id(), before.get_product_id())
+            {
+                inv.check_batch(conn)?;
+            }
+            if before.get_price() != inv.get_price() {
+                PriceChange::record_current(conn, &inv)?;
+            }
+            Ok(inv)
+        })
+    }
+}
+
+impl Updatable for NewBatch {
+    type Object = Batch;
+
+    fn update(&self, conn: &PgConnection, _id: &i32) -> Result<Batch, Error> {
+        diesel::update(batches.find(_id)).set(self).get_result(conn)
+    }
 }
 
 impl Deletable for Product {
@@ -155,3 +517,21 @@ impl Deletable for Inventory {
         diesel::delete(inventories.find(self.get_id())).get_result(conn)
     }
 }
+
+impl Deletable for Terpene {
+    fn delete(&self, conn: &PgConnection) -> Result<Terpene, Error> {
+        diesel::delete(terpenes.find(self.get_id())).get_result(conn)
+    }
+}
+
+impl Deletable for Batch {
+    fn delete(&self, conn: &PgConnection) -> Result<Batch, Error> {
+        diesel::delete(batches.find(self.get_id())).get_result(conn)
+    }
+}
+
+impl Deletable for LabResult {
+    fn delete(&self, conn: &PgConnection) -> Result<LabResult, Error> {
+        diesel::delete(lab_results.find(self.get_id())).get_result(conn)
+    }
+}
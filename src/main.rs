@@ -1,10 +1,10 @@
+use products::errors::query_error_handler;
 use products::handlers::*;
+use products::imports::MAX_IMPORT_BYTES;
+use products::pool::PoolConfig;
 
 use actix_web::{web, App, HttpServer};
 
-use diesel::pg::PgConnection;
-use diesel::r2d2::ConnectionManager;
-
 use handlebars::Handlebars;
 
 use std::env;
@@ -13,9 +13,8 @@ use std::env;
 async fn main() -> std::io::Result<()> {
     // set up db pool
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
-    let pool = r2d2::Pool::builder()
-        .build(manager)
+    let pool = PoolConfig::from_env()
+        .build(&database_url)
         .expect("Could not create pool.");
 
     // set up template rendering
@@ -31,14 +30,67 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .app_data(handlebars_ref.clone())
             .data(pool.clone())
+            .app_data(web::QueryConfig::default().error_handler(query_error_handler))
             .service(post_product)
+            .service(search_products)
             .service(get_product_id)
+            .service(get_similar_products)
+            .service(put_product)
+            .service(patch_product)
+            .service(delete_product)
             .service(post_cannabis)
             .service(get_cannabis_id)
+            .service(put_cannabis)
+            .service(patch_cannabis)
+            .service(delete_cannabis)
+            .service(post_cannabis_terpenes)
+            .service(get_cannabis_terpenes)
+            .service(delete_terpene)
+            .service(put_cannabis_parents)
+            .service(get_cannabis_lineage)
+            .service(get_cannabis_relatives)
+            .service(post_cannabis_batch)
+            .service(get_cannabis_batches)
+            .service(get_cannabis_batch)
+            .service(put_cannabis_batch)
+            .service(delete_cannabis_batch)
+            .service(get_batch_expiration)
+            .service(get_shelf_lives)
+            .service(put_shelf_life)
+            .service(delete_shelf_life)
+            .service(post_batch_recall)
+            .service(get_batch_recall)
+            .service(post_batch_lab_result)
+            .service(get_batch_lab_results)
+            .service(get_lab_result)
+            .service(get_lab_result_potency)
+            .service(put_lab_result_coa)
+            .service(get_lab_result_coa)
+            .service(delete_lab_result)
             .service(post_inventory)
             .service(get_product_inventory)
             .service(get_inventories)
+            .service(get_low_stock)
+            .service(get_expiring_inventories)
+            .service(put_inventory)
+            .service(patch_inventory)
+            .service(delete_inventory)
+            .service(post_inventory_movement)
+            .service(get_inventory_movements)
+            .service(post_inventory_price)
+            .service(get_inventory_prices)
+            .service(post_checkout)
+            .service(get_orders)
+            .service(get_order)
+            .service(patch_order)
             .service(get_products)
+            .service(
+                web::resource("/imports/products")
+                    .app_data(web::PayloadConfig::new(MAX_IMPORT_BYTES))
+                    .route(web::post().to(post_products_import)),
+            )
+            .service(export_inventory)
+            .service(export_products)
     })
     .bind("192.168.0.6:8888")?
     .run()
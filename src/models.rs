@@ -1,13 +1,25 @@
-use super::schema::{cannabis, inventories, products};
+use super::pagination::{bad_request, load_page_with, Page, PageParams, SortKey, Sortable};
+use super::schema::{
+    batches, cannabis, cannabis_parents, inventories, lab_results, order_lines, orders,
+    price_history, products, shelf_lives, stock_movements, terpenes,
+};
+use super::validation::FieldErrors;
 use super::Field;
 
+use chrono::{NaiveDate, NaiveDateTime};
 use diesel::pg::PgConnection;
-use diesel::sql_types::{Float, Integer, VarChar};
-use diesel::{sql_query, RunQueryDsl};
+use diesel::sql_types::{
+    Array, BigInt, Bool, Date, Double, Float, Integer, Nullable, Text, Timestamp, VarChar,
+};
+use diesel::{
+    sql_query, BelongingToDsl, Connection, ExpressionMethods, GroupedBy, QueryDsl, RunQueryDsl,
+};
 use diesel_derive_enum::DbEnum;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize, DbEnum)]
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, DbEnum)]
 pub enum Category {
     Flower,
     PreRoll,
@@ -32,7 +44,7 @@ impl Field<'static, Category> for Category {
     }
 }
 
-#[derive(Debug, DbEnum, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, DbEnum, Deserialize, Serialize)]
 pub enum Family {
     Indica,
     Sativa,
@@ -45,7 +57,40 @@ impl Field<'static, Family> for Family {
     }
 }
 
-#[derive(Serialize, Deserialize, Insertable)]
+/// Units a net weight can be recorded in. Eighths, quarters, halves and
+/// ounces use the dispensary convention of 28 g to the ounce.
+#[derive(Debug, Clone, Copy, PartialEq, Default, DbEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WeightUnit {
+    Mg,
+    #[default]
+    G,
+    Eighth,
+    Quarter,
+    Half,
+    Oz,
+}
+
+impl WeightUnit {
+    pub fn milligrams(&self) -> f64 {
+        match self {
+            WeightUnit::Mg => 1.0,
+            WeightUnit::G => 1_000.0,
+            WeightUnit::Eighth => 3_500.0,
+            WeightUnit::Quarter => 7_000.0,
+            WeightUnit::Half => 14_000.0,
+            WeightUnit::Oz => 28_000.0,
+        }
+    }
+}
+
+impl Field<'static, WeightUnit> for WeightUnit {
+    fn fields() -> Vec<&'static str> {
+        vec!["mg", "g", "eighth", "quarter", "half", "oz"]
+    }
+}
+
+#[derive(Serialize, Deserialize, Insertable, AsChangeset)]
 #[table_name = "products"]
 pub struct NewProduct {
     pub name: String,
@@ -59,9 +104,64 @@ impl NewProduct {
             category,
         }
     }
+
+    /// The product with `name` trimmed the way it is validated.
+    pub fn trimmed(self) -> Self {
+        NewProduct {
+            name: self.name.trim().to_owned(),
+            ..self
+        }
+    }
+
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.name("name", &self.name);
+        errors
+    }
+}
+
+#[derive(Debug, Default, Deserialize, AsChangeset)]
+#[table_name = "products"]
+pub struct ProductChanges {
+    pub name: Option<String>,
+    pub category: Option<Category>,
+}
+
+impl ProductChanges {
+    /// The changes with `name` trimmed the way it is validated.
+    pub fn trimmed(self) -> Self {
+        ProductChanges {
+            name: self.name.map(|n| n.trim().to_owned()),
+            ..self
+        }
+    }
+
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        if let Some(name) = &self.name {
+            errors.name("name", name);
+        }
+        errors
+    }
+}
+
+/// Slack allowed when comparing `thc + cbd` against `total_cannabinoids`, so
+/// values that only differ by float rounding are not rejected.
+const POTENCY_TOLERANCE: f32 = 0.001;
+
+fn check_potency(errors: &mut FieldErrors, thc: f32, cbd: f32, total: f32) {
+    errors.percentage("thc", thc);
+    errors.percentage("cbd", cbd);
+    errors.percentage("total_cannabinoids", total);
+    if errors.is_empty() && thc + cbd > total + POTENCY_TOLERANCE {
+        errors.add(
+            "total_cannabinoids",
+            "must be at least the sum of `thc` and `cbd`.",
+        );
+    }
 }
 
-#[derive(Serialize, Queryable, QueryableByName)]
+#[derive(Debug, Serialize, Identifiable, Queryable, QueryableByName)]
 #[table_name = "products"]
 pub struct Product {
     id: i32,
@@ -85,9 +185,416 @@ impl Product {
     pub fn get_category(&self) -> &Category {
         &self.category
     }
+
+    pub fn delete_cascade(
+        self,
+        conn: &PgConnection,
+    ) -> Result<DeletedProduct, diesel::result::Error> {
+        conn.transaction(|| {
+            let cannabis_deleted = cannabis::table
+                .filter(cannabis::product_id.eq(self.id))
+                .count()
+                .get_result(conn)?;
+            let terpenes_deleted = terpenes::table
+                .inner_join(cannabis::table)
+                .filter(cannabis::product_id.eq(self.id))
+                .count()
+                .get_result(conn)?;
+            let batches_deleted = batches::table
+                .inner_join(cannabis::table)
+                .filter(cannabis::product_id.eq(self.id))
+                .count()
+                .get_result(conn)?;
+            let inventories_deleted = inventories::table
+                .filter(inventories::product_id.eq(self.id))
+                .count()
+                .get_result(conn)?;
+            let product = diesel::delete(products::table.find(self.id)).get_result(conn)?;
+
+            Ok(DeletedProduct {
+                product,
+                cannabis_deleted,
+                terpenes_deleted,
+                batches_deleted,
+                inventories_deleted,
+            })
+        })
+    }
 }
 
-#[derive(Debug, Deserialize, Insertable)]
+impl Sortable for Product {
+    const SORT_KEYS: &'static [SortKey] = &[
+        SortKey {
+            name: "name",
+            sql_type: "varchar",
+        },
+        SortKey {
+            name: "id",
+            sql_type: "int4",
+        },
+    ];
+
+    fn cursor_id(&self) -> i32 {
+        self.id
+    }
+
+    fn sort_value(&self, key: &str) -> String {
+        match key {
+            "name" => self.name.to_string(),
+            _ => self.id.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeletedProduct {
+    product: Product,
+    cannabis_deleted: i64,
+    terpenes_deleted: i64,
+    batches_deleted: i64,
+    inventories_deleted: i64,
+}
+
+impl DeletedProduct {
+    pub fn get_product(&self) -> &Product {
+        &self.product
+    }
+
+    pub fn get_cannabis_deleted(&self) -> &i64 {
+        &self.cannabis_deleted
+    }
+
+    pub fn get_terpenes_deleted(&self) -> &i64 {
+        &self.terpenes_deleted
+    }
+
+    pub fn get_batches_deleted(&self) -> &i64 {
+        &self.batches_deleted
+    }
+
+    pub fn get_inventories_deleted(&self) -> &i64 {
+        &self.inventories_deleted
+    }
+}
+
+/// Relations that can be nested into a `ProductDetail`, and the unit
+/// expanded inventory reports its net weights in.
+#[derive(Debug, Default)]
+pub struct Expand {
+    pub cannabis: bool,
+    pub terpenes: bool,
+    pub batches: bool,
+    pub inventory: bool,
+    pub unit: Option<WeightUnit>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CannabisDetail {
+    #[serde(flatten)]
+    cannabis: Cannabis,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    terpenes: Option<Vec<Terpene>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    batches: Option<Vec<Batch>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProductDetail {
+    #[serde(flatten)]
+    product: Product,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cannabis: Option<Vec<CannabisDetail>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inventory: Option<Vec<InventoryResponse>>,
+}
+
+impl ProductDetail {
+    /// Loads a product and the requested relations. Every relation costs one
+    /// query no matter how many rows it has; terpenes and batches are grouped
+    /// under their cannabis record, so asking for either one loads cannabis.
+    /// Inventory is listed like `GET /products/{id}/inventory`, leaving out
+    /// rows that are recalled or past their shelf life.
+    pub fn load(
+        conn: &PgConnection,
+        _id: &i32,
+        expand: &Expand,
+    ) -> Result<ProductDetail, diesel::result::Error> {
+        let product: Product = products::table.find(_id).get_result(conn)?;
+
+        let cannabis = match expand.cannabis || expand.terpenes || expand.batches {
+            true => {
+                let cnbs: Vec<Cannabis> = Cannabis::belonging_to(&product).load(conn)?;
+                let mut terps = match expand.terpenes {
+                    true => Some(
+                        Terpene::belonging_to(&cnbs)
+                            .load::<Terpene>(conn)?
+                            .grouped_by(&cnbs)
+                            .into_iter(),
+                    ),
+                    false => None,
+                };
+                let mut bats = match expand.batches {
+                    true => Some(
+                        Batch::belonging_to(&cnbs)
+                            .load::<Batch>(conn)?
+                            .grouped_by(&cnbs)
+                            .into_iter(),
+                    ),
+                    false => None,
+                };
+                let details = cnbs
+                    .into_iter()
+                    .map(|cannabis| CannabisDetail {
+                        cannabis,
+                        terpenes: terps.as_mut().and_then(Iterator::next),
+                        batches: bats.as_mut().and_then(Iterator::next),
+                    })
+                    .collect();
+                Some(details)
+            }
+            false => None,
+        };
+
+        let inventory = match expand.inventory {
+            true => Some(
+                Inventory::with_product_id(conn, &product.id)?
+                    .into_iter()
+                    .map(|inv| inv.in_unit(expand.unit))
+                    .collect(),
+            ),
+            false => None,
+        };
+
+        Ok(ProductDetail {
+            product,
+            cannabis,
+            inventory,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct ProductMatch {
+    #[sql_type = "Integer"]
+    id: i32,
+
+    #[sql_type = "VarChar"]
+    name: String,
+
+    #[sql_type = "CategoryMapping"]
+    category: Category,
+
+    #[sql_type = "Float"]
+    score: f32,
+
+    #[sql_type = "Bool"]
+    prefix_match: bool,
+}
+
+impl ProductMatch {
+    pub fn get_id(&self) -> &i32 {
+        &self.id
+    }
+
+    pub fn get_score(&self) -> &f32 {
+        &self.score
+    }
+
+    pub fn is_prefix_match(&self) -> &bool {
+        &self.prefix_match
+    }
+
+    /// Fuzzy match on product name using the `pg_trgm` index. Names that
+    /// start with `q` rank first, then the rest by trigram similarity.
+    pub fn search(
+        conn: &PgConnection,
+        q: &str,
+        limit: i64,
+    ) -> Result<Vec<ProductMatch>, diesel::result::Error> {
+        let prefix = format!(
+            "{}%",
+            q.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        let _stmt = "SELECT p.id, p.name, p.category,
+                      similarity(p.name, $1) AS score,
+                      p.name ILIKE $2 AS prefix_match
+                     FROM products p
+                     WHERE p.name % $1 OR p.name ILIKE $2
+                     ORDER BY prefix_match DESC, score DESC, p.name
+                     LIMIT $3";
+        sql_query(_stmt)
+            .bind::<Text, _>(q)
+            .bind::<Text, _>(prefix)
+            .bind::<BigInt, _>(limit)
+            .get_results(conn)
+    }
+}
+
+/// Relative weight of each part of a cannabis profile when ranking similar
+/// products. Terpene differences are summed before `terpenes` is applied.
+#[derive(Debug, Clone, Copy)]
+pub struct SimilarityWeights {
+    pub terpenes: f64,
+    pub thc: f64,
+    pub cbd: f64,
+    pub total_cannabinoids: f64,
+}
+
+impl Default for SimilarityWeights {
+    fn default() -> Self {
+        SimilarityWeights {
+            terpenes: 1.0,
+            thc: 1.0,
+            cbd: 1.0,
+            total_cannabinoids: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct SimilarProduct {
+    #[sql_type = "Integer"]
+    id: i32,
+
+    #[sql_type = "VarChar"]
+    name: String,
+
+    #[sql_type = "CategoryMapping"]
+    category: Category,
+
+    #[sql_type = "Integer"]
+    cannabis_id: i32,
+
+    #[sql_type = "FamilyMapping"]
+    family: Family,
+
+    #[sql_type = "Float"]
+    thc: f32,
+
+    #[sql_type = "Float"]
+    cbd: f32,
+
+    #[sql_type = "Float"]
+    total_cannabinoids: f32,
+
+    #[sql_type = "Double"]
+    distance: f64,
+}
+
+impl SimilarProduct {
+    /// Other in-stock cannabis products ranked by weighted Euclidean distance
+    /// from the profile of `prod_id`, closest first. Each cannabis record is
+    /// compared by its most recent terpene profile, and a record without one
+    /// counts as all zeros. `category` defaults to that of `prod_id`.
+    ///
+    /// Every dimension is scaled by its range over the origin and the
+    /// candidates before it is weighted, and the five terpenes are averaged
+    /// into one dimension, so a weight means the same whatever the units.
+    pub fn rank(
+        conn: &PgConnection,
+        prod_id: &i32,
+        weights: SimilarityWeights,
+        category: Option<&Category>,
+        limit: i64,
+    ) -> Result<Vec<SimilarProduct>, diesel::result::Error> {
+        let prod = products::table.find(prod_id).get_result::<Product>(conn)?;
+        let origin = cannabis::table
+            .filter(cannabis::product_id.eq(prod_id))
+            .order(cannabis::id)
+            .first::<Cannabis>(conn)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => bad_request(&format!(
+                    "Product {} has no cannabis profile to compare.",
+                    prod_id
+                )),
+                e => e,
+            })?;
+
+        let _stmt = "WITH profile AS (
+                        SELECT DISTINCT ON (cannabis_id) *
+                        FROM terpenes ORDER BY cannabis_id, id DESC
+                     ), strain AS (
+                        SELECT c.id, c.product_id, c.family,
+                          c.thc, c.cbd, c.total_cannabinoids,
+                          COALESCE(t.myrcene, 0) AS myrcene,
+                          COALESCE(t.pinene, 0) AS pinene,
+                          COALESCE(t.limonene, 0) AS limonene,
+                          COALESCE(t.caryophyllene, 0) AS caryophyllene,
+                          COALESCE(t.terpinolene, 0) AS terpinolene
+                        FROM cannabis c LEFT JOIN profile t ON t.cannabis_id = c.id
+                     ), origin AS (
+                        SELECT * FROM strain WHERE id = $1
+                     ), candidate AS (
+                        SELECT s.*, p.name, p.category
+                        FROM strain s
+                        INNER JOIN products p ON s.product_id = p.id
+                        WHERE p.id <> (SELECT product_id FROM origin) AND p.category = $6
+                          AND EXISTS (
+                            SELECT 1 FROM inventories i
+                            WHERE i.product_id = p.id AND i.stock > 0
+                              AND NOT i.recalled AND NOT batch_expired(i.batch_id)
+                          )
+                     ), span AS (
+                        SELECT NULLIF(MAX(thc) - MIN(thc), 0) AS thc,
+                          NULLIF(MAX(cbd) - MIN(cbd), 0) AS cbd,
+                          NULLIF(MAX(total_cannabinoids) - MIN(total_cannabinoids), 0)
+                            AS total_cannabinoids,
+                          NULLIF(MAX(myrcene) - MIN(myrcene), 0) AS myrcene,
+                          NULLIF(MAX(pinene) - MIN(pinene), 0) AS pinene,
+                          NULLIF(MAX(limonene) - MIN(limonene), 0) AS limonene,
+                          NULLIF(MAX(caryophyllene) - MIN(caryophyllene), 0) AS caryophyllene,
+                          NULLIF(MAX(terpinolene) - MIN(terpinolene), 0) AS terpinolene
+                        FROM (
+                          SELECT thc, cbd, total_cannabinoids, myrcene, pinene,
+                            limonene, caryophyllene, terpinolene
+                          FROM candidate
+                          UNION ALL
+                          SELECT thc, cbd, total_cannabinoids, myrcene, pinene,
+                            limonene, caryophyllene, terpinolene
+                          FROM origin
+                        ) v
+                     )
+                     SELECT * FROM (
+                        SELECT DISTINCT ON (c.product_id) c.product_id AS id, c.name,
+                          c.category, c.id AS cannabis_id, c.family,
+                          c.thc, c.cbd, c.total_cannabinoids,
+                          SQRT(
+                            $2 * (COALESCE((c.myrcene - o.myrcene) / r.myrcene, 0) ^ 2
+                              + COALESCE((c.pinene - o.pinene) / r.pinene, 0) ^ 2
+                              + COALESCE((c.limonene - o.limonene) / r.limonene, 0) ^ 2
+                              + COALESCE((c.caryophyllene - o.caryophyllene)
+                                  / r.caryophyllene, 0) ^ 2
+                              + COALESCE((c.terpinolene - o.terpinolene)
+                                  / r.terpinolene, 0) ^ 2) / 5
+                            + $3 * COALESCE((c.thc - o.thc) / r.thc, 0) ^ 2
+                            + $4 * COALESCE((c.cbd - o.cbd) / r.cbd, 0) ^ 2
+                            + $5 * COALESCE((c.total_cannabinoids - o.total_cannabinoids)
+                                / r.total_cannabinoids, 0) ^ 2
+                          ) AS distance
+                        FROM candidate c CROSS JOIN origin o CROSS JOIN span r
+                        ORDER BY c.product_id, distance
+                     ) ranked
+                     ORDER BY distance, id
+                     LIMIT $7";
+        sql_query(_stmt)
+            .bind::<Integer, _>(origin.get_id())
+            .bind::<Double, _>(weights.terpenes)
+            .bind::<Double, _>(weights.thc)
+            .bind::<Double, _>(weights.cbd)
+            .bind::<Double, _>(weights.total_cannabinoids)
+            .bind::<CategoryMapping, _>(category.unwrap_or_else(|| prod.get_category()))
+            .bind::<BigInt, _>(limit)
+            .load(conn)
+    }
+}
+
+#[derive(Debug, Deserialize, Insertable, AsChangeset)]
 #[table_name = "cannabis"]
 pub struct NewCannabis {
     product_id: i32,
@@ -113,9 +620,80 @@ impl NewCannabis {
             total_cannabinoids,
         }
     }
+
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        check_potency(&mut errors, self.thc, self.cbd, self.total_cannabinoids);
+        errors
+    }
+}
+
+#[derive(Debug, Default, Deserialize, AsChangeset)]
+#[table_name = "cannabis"]
+pub struct CannabisChanges {
+    pub family: Option<Family>,
+    pub thc: Option<f32>,
+    pub cbd: Option<f32>,
+    pub total_cannabinoids: Option<f32>,
+}
+
+impl CannabisChanges {
+    /// Checks the values present. `thc + cbd <= total_cannabinoids` depends on
+    /// the stored row as well, so `update` enforces it.
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        let values = [
+            ("thc", self.thc),
+            ("cbd", self.cbd),
+            ("total_cannabinoids", self.total_cannabinoids),
+        ];
+        for (field, value) in values.iter() {
+            if let Some(value) = value {
+                errors.percentage(field, *value);
+            }
+        }
+        errors
+    }
+
+    /// Applies the changes to the locked record, rejecting them if the
+    /// merged potency no longer adds up.
+    pub fn update(&self, conn: &PgConnection, cnbs_id: &i32) -> Result<Cannabis, PotencyError> {
+        conn.transaction(|| {
+            let stored: Cannabis = cannabis::table
+                .find(cnbs_id)
+                .for_update()
+                .get_result(conn)?;
+            let mut errors = FieldErrors::default();
+            check_potency(
+                &mut errors,
+                self.thc.unwrap_or(stored.thc),
+                self.cbd.unwrap_or(stored.cbd),
+                self.total_cannabinoids.unwrap_or(stored.total_cannabinoids),
+            );
+            if !errors.is_empty() {
+                return Err(PotencyError::Invalid(errors));
+            }
+            Ok(diesel::update(cannabis::table.find(cnbs_id))
+                .set(self)
+                .get_result(conn)?)
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum PotencyError {
+    Invalid(FieldErrors),
+    Db(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for PotencyError {
+    fn from(e: diesel::result::Error) -> Self {
+        PotencyError::Db(e)
+    }
 }
 
-#[derive(Debug, Serialize, Queryable, QueryableByName)]
+#[derive(Debug, Serialize, Identifiable, Associations, Queryable, QueryableByName)]
+#[belongs_to(Product)]
 #[table_name = "cannabis"]
 pub struct Cannabis {
     id: i32,
@@ -152,58 +730,141 @@ impl Cannabis {
     }
 }
 
-#[derive(Debug, Deserialize, Insertable)]
-#[table_name = "inventories"]
-pub struct NewInventory {
-    product_id: i32,
-    stock: i32,
-    price: f32,
-    net_weight: f32,
+impl Sortable for Cannabis {
+    const SORT_KEYS: &'static [SortKey] = &[
+        SortKey {
+            name: "id",
+            sql_type: "int4",
+        },
+        SortKey {
+            name: "thc",
+            sql_type: "float4",
+        },
+        SortKey {
+            name: "cbd",
+            sql_type: "float4",
+        },
+        SortKey {
+            name: "total_cannabinoids",
+            sql_type: "float4",
+        },
+    ];
+
+    fn cursor_id(&self) -> i32 {
+        self.id
+    }
+
+    fn sort_value(&self, key: &str) -> String {
+        match key {
+            "thc" => self.thc.to_string(),
+            "cbd" => self.cbd.to_string(),
+            "total_cannabinoids" => self.total_cannabinoids.to_string(),
+            _ => self.id.to_string(),
+        }
+    }
 }
 
-impl NewInventory {
-    pub fn new(product_id: i32, stock: i32, price: f32, net_weight: f32) -> Self {
-        NewInventory {
-            product_id,
-            stock,
-            price,
-            net_weight,
+/// Body of `PUT /products/cannabis/{id}/parents`. Replaces every parent
+/// strain of the record.
+#[derive(Debug, Deserialize)]
+pub struct ParentsInput {
+    pub parent_ids: Vec<i32>,
+}
+
+impl ParentsInput {
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        let mut ids = self.parent_ids.clone();
+        ids.sort_unstable();
+        if ids.windows(2).any(|w| w[0] == w[1]) {
+            errors.add("parent_ids", "must not contain duplicates.");
         }
+        errors
     }
 }
 
-#[derive(Debug, Serialize, Queryable, QueryableByName)]
-#[table_name = "inventories"]
-pub struct Inventory {
-    id: i32,
-    product_id: i32,
-    stock: i32,
-    price: f32,
-    net_weight: f32,
+#[derive(Debug)]
+pub enum LineageError {
+    Cycle { parent_id: i32 },
+    Db(diesel::result::Error),
 }
-impl Inventory {
-    pub fn get_id(&self) -> &i32 {
-        &self.id
+
+impl From<diesel::result::Error> for LineageError {
+    fn from(e: diesel::result::Error) -> Self {
+        LineageError::Db(e)
     }
+}
 
-    pub fn with_product_id(
+#[derive(Debug, QueryableByName)]
+struct LineageEdge {
+    #[sql_type = "Integer"]
+    child_id: i32,
+
+    #[sql_type = "Integer"]
+    parent_id: i32,
+}
+
+/// A cannabis record as it appears in a lineage, named after its product.
+#[derive(Debug, Clone, Serialize, QueryableByName)]
+pub struct Strain {
+    #[sql_type = "Integer"]
+    cannabis_id: i32,
+
+    #[sql_type = "Integer"]
+    product_id: i32,
+
+    #[sql_type = "VarChar"]
+    name: String,
+
+    #[sql_type = "FamilyMapping"]
+    family: Family,
+}
+
+impl Strain {
+    pub fn with_ids(
         conn: &PgConnection,
-        prod_id: &i32,
-    ) -> Result<Vec<InventoryResponse>, diesel::result::Error> {
-        let _stmt = "SELECT
-                      i.id, i.product_id, p.name, p.category, i.stock, i.price, i.net_weight
-                    FROM inventories i INNER JOIN products p ON i.product_id = p.id
-                    WHERE p.id = $1";
-        sql_query(_stmt)
-            .bind::<Integer, _>(prod_id)
-            .get_results(conn)
+        ids: &[i32],
+    ) -> Result<Vec<Strain>, diesel::result::Error> {
+        let _stmt = "SELECT c.id AS cannabis_id, c.product_id, p.name, c.family
+                     FROM cannabis c INNER JOIN products p ON c.product_id = p.id
+                     WHERE c.id = ANY($1)
+                     ORDER BY c.id";
+        sql_query(_stmt).bind::<Array<Integer>, _>(ids).load(conn)
     }
 }
 
-#[derive(Debug, Serialize, Queryable, QueryableByName)]
-pub struct InventoryResponse {
+#[derive(Debug, Serialize)]
+pub struct LineageNode {
+    #[serde(flatten)]
+    strain: Strain,
+    parents: Vec<LineageNode>,
+}
+
+impl LineageNode {
+    fn grow(
+        strain: Strain,
+        edges: &[LineageEdge],
+        strains: &HashMap<i32, Strain>,
+        depth: i32,
+    ) -> LineageNode {
+        let parents = match depth {
+            0 => vec![],
+            _ => edges
+                .iter()
+                .filter(|e| e.child_id == strain.cannabis_id)
+                .filter_map(|e| strains.get(&e.parent_id))
+                .map(|parent| LineageNode::grow(parent.clone(), edges, strains, depth - 1))
+                .collect(),
+        };
+        LineageNode { strain, parents }
+    }
+}
+
+/// A product whose strain shares at least one ancestor with another.
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct Relative {
     #[sql_type = "Integer"]
-    id: i32,
+    cannabis_id: i32,
 
     #[sql_type = "Integer"]
     product_id: i32,
@@ -214,12 +875,2420 @@ pub struct InventoryResponse {
     #[sql_type = "CategoryMapping"]
     category: Category,
 
-    #[sql_type = "Integer"]
-    stock: i32,
+    #[sql_type = "BigInt"]
+    shared_ancestors: i64,
+}
 
-    #[sql_type = "Float"]
-    price: f32,
+impl Cannabis {
+    /// Replaces the parents of `cnbs_id`. The lineage table is locked for the
+    /// transaction so two concurrent assignments cannot close a cycle between
+    /// them, and a parent that descends from `cnbs_id` is rejected.
+    pub fn set_parents(
+        conn: &PgConnection,
+        cnbs_id: &i32,
+        input: ParentsInput,
+    ) -> Result<Vec<Strain>, LineageError> {
+        conn.transaction(|| {
+            sql_query("LOCK TABLE cannabis_parents IN SHARE ROW EXCLUSIVE MODE").execute(conn)?;
+            cannabis::table
+                .find(cnbs_id)
+                .select(cannabis::id)
+                .get_result::<i32>(conn)?;
+            diesel::delete(cannabis_parents::table.filter(cannabis_parents::child_id.eq(cnbs_id)))
+                .execute(conn)?;
 
-    #[sql_type = "Float"]
-    net_weight: f32,
+            let _stmt = "WITH RECURSIVE ancestors(root, id) AS (
+                            SELECT p, p FROM unnest($1::int4[]) AS p
+                            UNION
+                            SELECT a.root, cp.parent_id
+                            FROM ancestors a
+                            INNER JOIN cannabis_parents cp ON cp.child_id = a.id
+                         )
+                         SELECT $2 AS child_id, root AS parent_id
+                         FROM ancestors WHERE id = $2 LIMIT 1";
+            let cycle: Vec<LineageEdge> = sql_query(_stmt)
+                .bind::<Array<Integer>, _>(&input.parent_ids)
+                .bind::<Integer, _>(cnbs_id)
+                .load(conn)?;
+            if let Some(edge) = cycle.first() {
+                return Err(LineageError::Cycle {
+                    parent_id: edge.parent_id,
+                });
+            }
+
+            let rows = input
+                .parent_ids
+                .iter()
+                .map(|parent| {
+                    (
+                        cannabis_parents::child_id.eq(*cnbs_id),
+                        cannabis_parents::parent_id.eq(*parent),
+                    )
+                })
+                .collect::<Vec<_>>();
+            diesel::insert_into(cannabis_parents::table)
+                .values(rows)
+                .execute(conn)?;
+            Ok(Strain::with_ids(conn, &input.parent_ids)?)
+        })
+    }
+
+    /// The ancestor tree of `cnbs_id`, `depth` generations deep. A strain
+    /// reached through several parents is repeated under each of them.
+    pub fn lineage(
+        conn: &PgConnection,
+        cnbs_id: &i32,
+        depth: i32,
+    ) -> Result<LineageNode, diesel::result::Error> {
+        let root = Strain::with_ids(conn, &[*cnbs_id])?
+            .pop()
+            .ok_or(diesel::result::Error::NotFound)?;
+
+        let _stmt = "WITH RECURSIVE lineage(child_id, parent_id, depth) AS (
+                        SELECT child_id, parent_id, 1 FROM cannabis_parents WHERE child_id = $1
+                        UNION
+                        SELECT cp.child_id, cp.parent_id, l.depth + 1
+                        FROM lineage l
+                        INNER JOIN cannabis_parents cp ON cp.child_id = l.parent_id
+                        WHERE l.depth < $2
+                     )
+                     SELECT DISTINCT child_id, parent_id FROM lineage
+                     ORDER BY child_id, parent_id";
+        let edges: Vec<LineageEdge> = sql_query(_stmt)
+            .bind::<Integer, _>(cnbs_id)
+            .bind::<Integer, _>(depth)
+            .load(conn)?;
+        let ids = edges.iter().map(|e| e.parent_id).collect::<Vec<_>>();
+        let strains = Strain::with_ids(conn, &ids)?
+            .into_iter()
+            .map(|s| (s.cannabis_id, s))
+            .collect::<HashMap<_, _>>();
+
+        Ok(LineageNode::grow(root, &edges, &strains, depth))
+    }
+
+    /// Other products whose strains share an ancestor with `cnbs_id`, most
+    /// shared ancestors first.
+    /// Only the ancestors of `cnbs_id` and their descendants are walked, not
+    /// the whole lineage table.
+    pub fn relatives(
+        conn: &PgConnection,
+        cnbs_id: &i32,
+    ) -> Result<Vec<Relative>, diesel::result::Error> {
+        let _stmt = "WITH RECURSIVE mine(id) AS (
+                        SELECT parent_id FROM cannabis_parents WHERE child_id = $1
+                        UNION
+                        SELECT cp.parent_id
+                        FROM mine m
+                        INNER JOIN cannabis_parents cp ON cp.child_id = m.id
+                     ), kin(ancestor, id) AS (
+                        SELECT cp.parent_id, cp.child_id
+                        FROM cannabis_parents cp
+                        INNER JOIN mine m ON m.id = cp.parent_id
+                        UNION
+                        SELECT k.ancestor, cp.child_id
+                        FROM kin k
+                        INNER JOIN cannabis_parents cp ON cp.parent_id = k.id
+                     )
+                     SELECT c.id AS cannabis_id, c.product_id, p.name, p.category,
+                       COUNT(DISTINCT kin.ancestor) AS shared_ancestors
+                     FROM kin
+                     INNER JOIN cannabis c ON c.id = kin.id
+                     INNER JOIN products p ON c.product_id = p.id
+                     WHERE kin.id <> $1
+                     GROUP BY c.id, c.product_id, p.name, p.category
+                     ORDER BY shared_ancestors DESC, c.id";
+        sql_query(_stmt).bind::<Integer, _>(cnbs_id).load(conn)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TerpeneInput {
+    #[serde(default)]
+    pub myrcene: f32,
+    #[serde(default)]
+    pub pinene: f32,
+    #[serde(default)]
+    pub limonene: f32,
+    #[serde(default)]
+    pub caryophyllene: f32,
+    #[serde(default)]
+    pub terpinolene: f32,
+}
+
+impl TerpeneInput {
+    pub fn values(&self) -> [(&'static str, f32); 5] {
+        [
+            ("myrcene", self.myrcene),
+            ("pinene", self.pinene),
+            ("limonene", self.limonene),
+            ("caryophyllene", self.caryophyllene),
+            ("terpinolene", self.terpinolene),
+        ]
+    }
+
+    /// Each value must be a percentage and together they cannot exceed 100%.
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        check_terpenes(&mut errors, self);
+        errors
+    }
+
+    pub fn into_new_terpene(self, cannabis_id: i32) -> NewTerpene {
+        NewTerpene::new(
+            cannabis_id,
+            self.myrcene,
+            self.pinene,
+            self.limonene,
+            self.caryophyllene,
+            self.terpinolene,
+        )
+    }
+}
+
+fn check_terpenes(errors: &mut FieldErrors, terpenes: &TerpeneInput) {
+    let values = terpenes.values();
+    for (field, value) in values.iter() {
+        errors.percentage(field, *value);
+    }
+    if values.iter().map(|(_, v)| v).sum::<f32>() > 100.0 {
+        errors.add("terpenes", "must not total more than 100%.");
+    }
+}
+
+#[derive(Debug, Deserialize, Insertable)]
+#[table_name = "terpenes"]
+pub struct NewTerpene {
+    cannabis_id: i32,
+    myrcene: f32,
+    pinene: f32,
+    limonene: f32,
+    caryophyllene: f32,
+    terpinolene: f32,
+}
+
+impl NewTerpene {
+    pub fn new(
+        cannabis_id: i32,
+        myrcene: f32,
+        pinene: f32,
+        limonene: f32,
+        caryophyllene: f32,
+        terpinolene: f32,
+    ) -> Self {
+        NewTerpene {
+            cannabis_id,
+            myrcene,
+            pinene,
+            limonene,
+            caryophyllene,
+            terpinolene,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Identifiable, Associations, Queryable, QueryableByName)]
+#[belongs_to(Cannabis)]
+#[table_name = "terpenes"]
+pub struct Terpene {
+    id: i32,
+    cannabis_id: i32,
+    myrcene: f32,
+    pinene: f32,
+    limonene: f32,
+    caryophyllene: f32,
+    terpinolene: f32,
+}
+
+impl Terpene {
+    pub fn get_id(&self) -> &i32 {
+        &self.id
+    }
+
+    pub fn get_cannabis_id(&self) -> &i32 {
+        &self.cannabis_id
+    }
+
+    pub fn get_myrcene(&self) -> &f32 {
+        &self.myrcene
+    }
+
+    pub fn get_pinene(&self) -> &f32 {
+        &self.pinene
+    }
+
+    pub fn get_limonene(&self) -> &f32 {
+        &self.limonene
+    }
+
+    pub fn get_caryophyllene(&self) -> &f32 {
+        &self.caryophyllene
+    }
+
+    pub fn get_terpinolene(&self) -> &f32 {
+        &self.terpinolene
+    }
+
+    pub fn with_cannabis_id(
+        conn: &PgConnection,
+        cnbs_id: &i32,
+    ) -> Result<Vec<Terpene>, diesel::result::Error> {
+        terpenes::table
+            .filter(terpenes::cannabis_id.eq(cnbs_id))
+            .order(terpenes::id)
+            .load(conn)
+    }
+}
+
+impl Sortable for Terpene {
+    const SORT_KEYS: &'static [SortKey] = &[
+        SortKey {
+            name: "id",
+            sql_type: "int4",
+        },
+        SortKey {
+            name: "myrcene",
+            sql_type: "float4",
+        },
+        SortKey {
+            name: "pinene",
+            sql_type: "float4",
+        },
+        SortKey {
+            name: "limonene",
+            sql_type: "float4",
+        },
+        SortKey {
+            name: "caryophyllene",
+            sql_type: "float4",
+        },
+        SortKey {
+            name: "terpinolene",
+            sql_type: "float4",
+        },
+    ];
+
+    fn cursor_id(&self) -> i32 {
+        self.id
+    }
+
+    fn sort_value(&self, key: &str) -> String {
+        match key {
+            "myrcene" => self.myrcene.to_string(),
+            "pinene" => self.pinene.to_string(),
+            "limonene" => self.limonene.to_string(),
+            "caryophyllene" => self.caryophyllene.to_string(),
+            "terpinolene" => self.terpinolene.to_string(),
+            _ => self.id.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchInput {
+    pub harvest_date: NaiveDate,
+    pub package_date: NaiveDate,
+    pub final_test_date: NaiveDate,
+}
+
+impl BatchInput {
+    /// The dates must be in order: harvest, then package, then final test.
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        if self.harvest_date >= self.package_date {
+            errors.add("harvest_date", "must come before `package_date`.");
+        }
+        if self.package_date >= self.final_test_date {
+            errors.add("package_date", "must come before `final_test_date`.");
+        }
+        errors
+    }
+
+    pub fn into_new_batch(self, cannabis_id: i32) -> NewBatch {
+        NewBatch::new(
+            cannabis_id,
+            self.harvest_date,
+            self.package_date,
+            self.final_test_date,
+        )
+    }
+}
+
+#[derive(Debug, Deserialize, Insertable, AsChangeset)]
+#[table_name = "batches"]
+pub struct NewBatch {
+    cannabis_id: i32,
+    harvest_date: NaiveDate,
+    package_date: NaiveDate,
+    final_test_date: NaiveDate,
+}
+
+impl NewBatch {
+    pub fn new(
+        cannabis_id: i32,
+        harvest_date: NaiveDate,
+        package_date: NaiveDate,
+        final_test_date: NaiveDate,
+    ) -> Self {
+        NewBatch {
+            cannabis_id,
+            harvest_date,
+            package_date,
+            final_test_date,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Identifiable, Associations, Queryable, QueryableByName)]
+#[belongs_to(Cannabis)]
+#[table_name = "batches"]
+pub struct Batch {
+    id: i32,
+    cannabis_id: i32,
+    harvest_date: NaiveDate,
+    package_date: NaiveDate,
+    final_test_date: NaiveDate,
+    recalled_at: Option<NaiveDateTime>,
+    recall_reason: Option<String>,
+}
+
+impl Batch {
+    pub fn get_id(&self) -> &i32 {
+        &self.id
+    }
+
+    pub fn get_cannabis_id(&self) -> &i32 {
+        &self.cannabis_id
+    }
+
+    pub fn get_harvest_date(&self) -> &NaiveDate {
+        &self.harvest_date
+    }
+
+    pub fn get_package_date(&self) -> &NaiveDate {
+        &self.package_date
+    }
+
+    pub fn get_final_test_date(&self) -> &NaiveDate {
+        &self.final_test_date
+    }
+
+    pub fn get_recalled_at(&self) -> Option<&NaiveDateTime> {
+        self.recalled_at.as_ref()
+    }
+
+    pub fn with_cannabis_id(
+        conn: &PgConnection,
+        cnbs_id: &i32,
+    ) -> Result<Vec<Batch>, diesel::result::Error> {
+        batches::table
+            .filter(batches::cannabis_id.eq(cnbs_id))
+            .order(batches::harvest_date)
+            .load(conn)
+    }
+
+    /// Looks up a batch by id, but only if it belongs to `cnbs_id`.
+    pub fn of_cannabis(
+        conn: &PgConnection,
+        cnbs_id: &i32,
+        _id: &i32,
+    ) -> Result<Batch, diesel::result::Error> {
+        batches::table
+            .filter(batches::cannabis_id.eq(cnbs_id))
+            .find(_id)
+            .get_result(conn)
+    }
+}
+
+impl Sortable for Batch {
+    const SORT_KEYS: &'static [SortKey] = &[
+        SortKey {
+            name: "id",
+            sql_type: "int4",
+        },
+        SortKey {
+            name: "harvest_date",
+            sql_type: "date",
+        },
+        SortKey {
+            name: "package_date",
+            sql_type: "date",
+        },
+        SortKey {
+            name: "final_test_date",
+            sql_type: "date",
+        },
+    ];
+
+    fn cursor_id(&self) -> i32 {
+        self.id
+    }
+
+    fn sort_value(&self, key: &str) -> String {
+        match key {
+            "harvest_date" => self.harvest_date.to_string(),
+            "package_date" => self.package_date.to_string(),
+            "final_test_date" => self.final_test_date.to_string(),
+            _ => self.id.to_string(),
+        }
+    }
+}
+
+/// Body of `POST /batches/{id}/recall`.
+#[derive(Debug, Deserialize)]
+pub struct RecallInput {
+    pub reason: String,
+}
+
+impl RecallInput {
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.required("reason", &self.reason);
+        errors
+    }
+}
+
+#[derive(Debug)]
+pub enum RecallError {
+    AlreadyRecalled { batch_id: i32 },
+    Db(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for RecallError {
+    fn from(e: diesel::result::Error) -> Self {
+        RecallError::Db(e)
+    }
+}
+
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct RecalledInventory {
+    #[sql_type = "Integer"]
+    id: i32,
+
+    #[sql_type = "Integer"]
+    product_id: i32,
+
+    #[sql_type = "VarChar"]
+    name: String,
+
+    #[sql_type = "Integer"]
+    stock: i32,
+}
+
+/// A customer who bought from a recalled batch. `user_id` is the id in the
+/// users service; cancelled orders are left out.
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct AffectedCustomer {
+    #[sql_type = "Integer"]
+    user_id: i32,
+
+    #[sql_type = "BigInt"]
+    orders: i64,
+
+    #[sql_type = "BigInt"]
+    units: i64,
+
+    #[sql_type = "Timestamp"]
+    last_ordered_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecallReport {
+    batch: Batch,
+    units_on_hand: i64,
+    inventory: Vec<RecalledInventory>,
+    customers: Vec<AffectedCustomer>,
+}
+
+impl RecallReport {
+    pub fn get_units_on_hand(&self) -> &i64 {
+        &self.units_on_hand
+    }
+
+    pub fn get_customers(&self) -> &Vec<AffectedCustomer> {
+        &self.customers
+    }
+
+    /// Stock still on hand from `batch` and everyone who bought from it.
+    pub fn of_batch(
+        conn: &PgConnection,
+        batch: Batch,
+    ) -> Result<RecallReport, diesel::result::Error> {
+        let _stmt = "SELECT i.id, i.product_id, p.name, i.stock
+                     FROM inventories i INNER JOIN products p ON i.product_id = p.id
+                     WHERE i.batch_id = $1
+                     ORDER BY i.id";
+        let inventory: Vec<RecalledInventory> =
+            sql_query(_stmt).bind::<Integer, _>(batch.id).load(conn)?;
+
+        let _stmt = "SELECT o.user_id, COUNT(DISTINCT o.id) AS orders,
+                       SUM(l.quantity)::int8 AS units, MAX(o.created_at) AS last_ordered_at
+                     FROM order_lines l
+                     INNER JOIN orders o ON l.order_id = o.id
+                     INNER JOIN inventories i ON l.inventory_id = i.id
+                     WHERE i.batch_id = $1 AND o.status <> 'cancelled'
+                     GROUP BY o.user_id
+                     ORDER BY o.user_id";
+        let customers = sql_query(_stmt).bind::<Integer, _>(batch.id).load(conn)?;
+
+        Ok(RecallReport {
+            units_on_hand: inventory.iter().map(|i| i64::from(i.stock)).sum(),
+            batch,
+            inventory,
+            customers,
+        })
+    }
+}
+
+impl Batch {
+    /// Marks the batch recalled and locks every inventory row packed from it
+    /// out of listings and checkout, all in one transaction. The stock itself
+    /// is left on the rows so it can be counted and written off.
+    pub fn recall(
+        conn: &PgConnection,
+        _id: &i32,
+        input: RecallInput,
+    ) -> Result<RecallReport, RecallError> {
+        conn.transaction(|| {
+            let batch: Batch = batches::table.find(_id).for_update().get_result(conn)?;
+            if batch.recalled_at.is_some() {
+                return Err(RecallError::AlreadyRecalled { batch_id: batch.id });
+            }
+            let batch: Batch = diesel::update(batches::table.find(_id))
+                .set((
+                    batches::recalled_at.eq(diesel::dsl::now),
+                    batches::recall_reason.eq(input.reason.trim()),
+                ))
+                .get_result(conn)?;
+            diesel::update(inventories::table.filter(inventories::batch_id.eq(_id)))
+                .set(inventories::recalled.eq(true))
+                .execute(conn)?;
+            Ok(RecallReport::of_batch(conn, batch)?)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, DbEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestOutcome {
+    Pass,
+    Fail,
+}
+
+impl Field<'static, TestOutcome> for TestOutcome {
+    fn fields() -> Vec<&'static str> {
+        vec!["pass", "fail"]
+    }
+}
+
+/// Body of `POST /batches/{id}/lab-results`, transcribed from a certificate
+/// of analysis. Terpenes left out were not detected.
+#[derive(Debug, Deserialize)]
+pub struct LabResultInput {
+    pub lab_name: String,
+    pub tested_on: NaiveDate,
+    pub thc: f32,
+    pub cbd: f32,
+    pub total_cannabinoids: f32,
+    #[serde(default)]
+    pub terpenes: TerpeneInput,
+    pub pesticides: TestOutcome,
+    pub heavy_metals: TestOutcome,
+    pub microbials: TestOutcome,
+    pub residual_solvents: TestOutcome,
+}
+
+impl LabResultInput {
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.name("lab_name", &self.lab_name);
+        check_potency(&mut errors, self.thc, self.cbd, self.total_cannabinoids);
+        check_terpenes(&mut errors, &self.terpenes);
+        errors
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "lab_results"]
+struct NewLabResult {
+    batch_id: i32,
+    lab_name: String,
+    tested_on: NaiveDate,
+    thc: f32,
+    cbd: f32,
+    total_cannabinoids: f32,
+    myrcene: f32,
+    pinene: f32,
+    limonene: f32,
+    caryophyllene: f32,
+    terpinolene: f32,
+    pesticides: TestOutcome,
+    heavy_metals: TestOutcome,
+    microbials: TestOutcome,
+    residual_solvents: TestOutcome,
+}
+
+#[derive(Debug, Serialize, Identifiable, Associations, Queryable, QueryableByName)]
+#[belongs_to(Batch)]
+#[table_name = "lab_results"]
+pub struct LabResult {
+    id: i32,
+    batch_id: i32,
+    lab_name: String,
+    tested_on: NaiveDate,
+    thc: f32,
+    cbd: f32,
+    total_cannabinoids: f32,
+    myrcene: f32,
+    pinene: f32,
+    limonene: f32,
+    caryophyllene: f32,
+    terpinolene: f32,
+    pesticides: TestOutcome,
+    heavy_metals: TestOutcome,
+    microbials: TestOutcome,
+    residual_solvents: TestOutcome,
+    #[serde(skip_serializing)]
+    coa_path: Option<String>,
+    coa_content_type: Option<String>,
+    created_at: NaiveDateTime,
+}
+
+/// Measured THC of a lab result against the `cannabis.thc` on its label.
+/// `tolerance` is a percentage of the label value, and `deviation` is in
+/// percentage points.
+#[derive(Debug, Serialize)]
+pub struct PotencyCheck {
+    lab_result_id: i32,
+    cannabis_id: i32,
+    label_thc: f32,
+    measured_thc: f32,
+    deviation: f32,
+    tolerance: f32,
+    within_tolerance: bool,
+}
+
+impl PotencyCheck {
+    pub fn within_tolerance(&self) -> bool {
+        self.within_tolerance
+    }
+}
+
+/// A record whose delete takes lab results, and their COA documents, with it.
+#[derive(Debug, Clone, Copy)]
+pub enum CoaOwner {
+    Batch(i32),
+    Cannabis(i32),
+    Product(i32),
+}
+
+impl LabResult {
+    pub fn get_id(&self) -> &i32 {
+        &self.id
+    }
+
+    pub fn get_coa_path(&self) -> Option<&str> {
+        self.coa_path.as_deref()
+    }
+
+    pub fn get_coa_content_type(&self) -> Option<&str> {
+        self.coa_content_type.as_deref()
+    }
+
+    /// Whether every contaminant screen passed.
+    pub fn passed(&self) -> bool {
+        [
+            self.pesticides,
+            self.heavy_metals,
+            self.microbials,
+            self.residual_solvents,
+        ]
+        .iter()
+        .all(|outcome| *outcome == TestOutcome::Pass)
+    }
+
+    pub fn record(
+        conn: &PgConnection,
+        batch_id: &i32,
+        input: LabResultInput,
+    ) -> Result<LabResult, diesel::result::Error> {
+        let batch = batches::table.find(batch_id).get_result::<Batch>(conn)?;
+        let new = NewLabResult {
+            batch_id: *batch.get_id(),
+            lab_name: input.lab_name.trim().to_owned(),
+            tested_on: input.tested_on,
+            thc: input.thc,
+            cbd: input.cbd,
+            total_cannabinoids: input.total_cannabinoids,
+            myrcene: input.terpenes.myrcene,
+            pinene: input.terpenes.pinene,
+            limonene: input.terpenes.limonene,
+            caryophyllene: input.terpenes.caryophyllene,
+            terpinolene: input.terpenes.terpinolene,
+            pesticides: input.pesticides,
+            heavy_metals: input.heavy_metals,
+            microbials: input.microbials,
+            residual_solvents: input.residual_solvents,
+        };
+        diesel::insert_into(lab_results::table)
+            .values(&new)
+            .get_result(conn)
+    }
+
+    pub fn with_batch_id(
+        conn: &PgConnection,
+        batch_id: &i32,
+    ) -> Result<Vec<LabResult>, diesel::result::Error> {
+        let batch = batches::table.find(batch_id).get_result::<Batch>(conn)?;
+        LabResult::belonging_to(&batch)
+            .order((lab_results::tested_on, lab_results::id))
+            .load(conn)
+    }
+
+    /// Points lab result `_id` at a newly stored COA. Returns the updated
+    /// result and the path of the document it replaced, which the caller
+    /// removes once this has gone through.
+    pub fn attach_coa(
+        conn: &PgConnection,
+        _id: &i32,
+        path: &str,
+        content_type: &str,
+    ) -> Result<(LabResult, Option<String>), diesel::result::Error> {
+        conn.transaction(|| {
+            let before: LabResult = lab_results::table.find(_id).for_update().get_result(conn)?;
+            let after = diesel::update(lab_results::table.find(_id))
+                .set((
+                    lab_results::coa_path.eq(path),
+                    lab_results::coa_content_type.eq(content_type),
+                ))
+                .get_result(conn)?;
+            Ok((after, before.coa_path))
+        })
+    }
+
+    /// Deletes every lab result under `owner` and then runs `delete`, in one
+    /// transaction, so no COA can be attached in between. Returns what
+    /// `delete` returns and the paths of the COA documents the lab results
+    /// pointed at, which the caller removes once this has gone through.
+    pub fn delete_with_coas<T, E>(
+        conn: &PgConnection,
+        owner: CoaOwner,
+        delete: impl FnOnce() -> Result<T, E>,
+    ) -> Result<(T, Vec<String>), E>
+    where
+        E: From<diesel::result::Error>,
+    {
+        conn.transaction(|| {
+            let query = diesel::delete(lab_results::table).into_boxed();
+            let query = match owner {
+                CoaOwner::Batch(_id) => query.filter(lab_results::batch_id.eq(_id)),
+                CoaOwner::Cannabis(_id) => query.filter(
+                    lab_results::batch_id.eq_any(
+                        batches::table
+                            .filter(batches::cannabis_id.eq(_id))
+                            .select(batches::id),
+                    ),
+                ),
+                CoaOwner::Product(_id) => query.filter(
+                    lab_results::batch_id.eq_any(
+                        batches::table
+                            .filter(
+                                batches::cannabis_id.eq_any(
+                                    cannabis::table
+                                        .filter(cannabis::product_id.eq(_id))
+                                        .select(cannabis::id),
+                                ),
+                            )
+                            .select(batches::id),
+                    ),
+                ),
+            };
+            let paths = query
+                .returning(lab_results::coa_path)
+                .get_results::<Option<String>>(conn)?;
+            let deleted = delete()?;
+            Ok((deleted, paths.into_iter().flatten().collect()))
+        })
+    }
+
+    /// Compares measured THC against the label of the batch's cannabis
+    /// record. The result is within tolerance when the two differ by no
+    /// more than `tolerance` percent of the label value.
+    pub fn potency_check(
+        &self,
+        conn: &PgConnection,
+        tolerance: f32,
+    ) -> Result<PotencyCheck, diesel::result::Error> {
+        let (cannabis_id, label_thc) = batches::table
+            .inner_join(cannabis::table)
+            .filter(batches::id.eq(self.batch_id))
+            .select((cannabis::id, cannabis::thc))
+            .get_result::<(i32, f32)>(conn)?;
+        let deviation = self.thc - label_thc;
+        let allowed = label_thc * tolerance / 100.0;
+
+        Ok(PotencyCheck {
+            lab_result_id: self.id,
+            cannabis_id,
+            label_thc,
+            measured_thc: self.thc,
+            deviation,
+            tolerance,
+            within_tolerance: deviation.abs() <= allowed + POTENCY_TOLERANCE,
+        })
+    }
+}
+
+impl Sortable for LabResult {
+    const SORT_KEYS: &'static [SortKey] = &[
+        SortKey {
+            name: "id",
+            sql_type: "int4",
+        },
+        SortKey {
+            name: "tested_on",
+            sql_type: "date",
+        },
+    ];
+
+    fn cursor_id(&self) -> i32 {
+        self.id
+    }
+
+    fn sort_value(&self, key: &str) -> String {
+        match key {
+            "tested_on" => self.tested_on.to_string(),
+            _ => self.id.to_string(),
+        }
+    }
+}
+
+/// A net weight together with the unit it was recorded in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Weight {
+    amount: f32,
+    unit: WeightUnit,
+}
+
+impl Weight {
+    pub fn new(amount: f32, unit: WeightUnit) -> Self {
+        Weight { amount, unit }
+    }
+
+    pub fn get_amount(&self) -> &f32 {
+        &self.amount
+    }
+
+    pub fn get_unit(&self) -> &WeightUnit {
+        &self.unit
+    }
+
+    pub fn milligrams(&self) -> f64 {
+        f64::from(self.amount) * self.unit.milligrams()
+    }
+
+    pub fn to(&self, unit: WeightUnit) -> Weight {
+        Weight {
+            amount: (self.milligrams() / unit.milligrams()) as f32,
+            unit,
+        }
+    }
+}
+
+/// Currency assumed when an inventory row does not name one.
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+/// An exact amount of money in minor units (cents) of an ISO 4217 currency.
+/// Prices are never held as floats, and arithmetic refuses to mix currencies
+/// or overflow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Money {
+    cents: i64,
+    currency: String,
+}
+
+impl Money {
+    pub fn new(cents: i64, currency: &str) -> Result<Self, String> {
+        Ok(Money {
+            cents,
+            currency: parse_currency(currency)?,
+        })
+    }
+
+    /// Parses a decimal amount such as `"15.10"`, `"15.1"` or `"15"` without
+    /// going through floating point. More than two decimal places are only
+    /// accepted when the extra digits are zeros.
+    pub fn parse(amount: &str, currency: &str) -> Result<Self, String> {
+        Money::new(parse_cents(amount)?, currency)
+    }
+
+    pub fn get_cents(&self) -> &i64 {
+        &self.cents
+    }
+
+    pub fn get_currency(&self) -> &str {
+        &self.currency
+    }
+
+    pub fn checked_add(&self, other: &Money) -> Result<Money, String> {
+        if self.currency != other.currency {
+            return Err(format!(
+                "Cannot add {} to {}.",
+                other.currency, self.currency
+            ));
+        }
+        self.cents
+            .checked_add(other.cents)
+            .map(|cents| self.with_cents(cents))
+            .ok_or_else(|| "Amount is out of range.".to_owned())
+    }
+
+    pub fn checked_mul(&self, quantity: i64) -> Result<Money, String> {
+        self.cents
+            .checked_mul(quantity)
+            .map(|cents| self.with_cents(cents))
+            .ok_or_else(|| "Amount is out of range.".to_owned())
+    }
+
+    /// Takes `basis_points` hundredths of a percent off, rounding the
+    /// discount half up to the nearest cent.
+    pub fn discount(&self, basis_points: u32) -> Result<Money, String> {
+        if basis_points > 10_000 {
+            return Err("A discount cannot exceed 100%.".to_owned());
+        }
+        let off = (i128::from(self.cents) * i128::from(basis_points) + 5_000) / 10_000;
+        Ok(self.with_cents(self.cents - off as i64))
+    }
+
+    /// The price of one gram at this price for `weight`, rounded half up to
+    /// the nearest cent. `None` when the weight rounds to zero milligrams.
+    pub fn per_gram(&self, weight: &Weight) -> Option<Money> {
+        let mg = weight.milligrams().round() as i128;
+        if mg <= 0 {
+            return None;
+        }
+        let cents = (i128::from(self.cents) * 1_000 * 2 + mg) / (mg * 2);
+        Some(self.with_cents(cents as i64))
+    }
+
+    fn with_cents(&self, cents: i64) -> Money {
+        Money {
+            cents,
+            currency: self.currency.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}", format_cents(self.cents), self.currency)
+    }
+}
+
+fn parse_currency(code: &str) -> Result<String, String> {
+    let code = code.trim().to_ascii_uppercase();
+    match code.len() == 3 && code.bytes().all(|b| b.is_ascii_uppercase()) {
+        true => Ok(code),
+        false => Err(format!("`{}` is not a three-letter currency code.", code)),
+    }
+}
+
+fn parse_cents(amount: &str) -> Result<i64, String> {
+    let invalid = || format!("`{}` is not a valid amount.", amount);
+    let trimmed = amount.trim();
+    let (negative, digits) = match trimmed.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, trimmed),
+    };
+    let (whole, frac) = match digits.split_once('.') {
+        Some((whole, frac)) => (whole, frac),
+        None => (digits, ""),
+    };
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if whole.is_empty() || !is_digits(whole) || !is_digits(frac) {
+        return Err(invalid());
+    }
+    if frac.len() > 2 && frac[2..].bytes().any(|b| b != b'0') {
+        return Err(format!("`{}` has more than two decimal places.", amount));
+    }
+
+    let frac = format!("{:0<2}", frac.get(..2).unwrap_or(frac));
+    let cents = whole
+        .parse::<i64>()
+        .ok()
+        .and_then(|w| w.checked_mul(100))
+        .and_then(|w| w.checked_add(frac.parse::<i64>().ok()?))
+        .ok_or_else(invalid)?;
+    Ok(if negative { -cents } else { cents })
+}
+
+fn format_cents(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    let abs = cents.unsigned_abs();
+    format!("{}{}.{:02}", sign, abs / 100, abs % 100)
+}
+
+/// Accepts a price as a decimal string or a JSON number and returns it in
+/// cents. Numbers are read back from their shortest textual form, so `15.1`
+/// becomes exactly 1510.
+fn deserialize_price<'de, D>(deserializer: D) -> Result<i64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Amount {
+        Text(String),
+        Number(f64),
+    }
+
+    let amount = match Amount::deserialize(deserializer)? {
+        Amount::Text(text) => text,
+        Amount::Number(number) => number.to_string(),
+    };
+    parse_cents(&amount).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_optional_price<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_price(deserializer).map(Some)
+}
+
+fn deserialize_currency<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    parse_currency(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_optional_currency<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_currency(deserializer).map(Some)
+}
+
+/// Keeps an explicit `null` apart from a missing field, which `#[serde(default)]`
+/// turns into `None`.
+fn deserialize_nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+fn serialize_price<S>(cents: &i64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&format_cents(*cents))
+}
+
+fn default_currency() -> String {
+    DEFAULT_CURRENCY.to_owned()
+}
+
+#[derive(Debug, Deserialize, Insertable, AsChangeset)]
+#[table_name = "inventories"]
+pub struct NewInventory {
+    product_id: i32,
+    stock: i32,
+    #[serde(rename = "price", deserialize_with = "deserialize_price")]
+    price_cents: i64,
+    #[serde(
+        default = "default_currency",
+        deserialize_with = "deserialize_currency"
+    )]
+    currency: String,
+    net_weight: f32,
+    #[serde(default)]
+    net_weight_unit: WeightUnit,
+    #[serde(default)]
+    reorder_threshold: Option<i32>,
+    #[serde(default)]
+    batch_id: Option<i32>,
+}
+
+impl NewInventory {
+    pub fn new(product_id: i32, stock: i32, price: Money, net_weight: Weight) -> Self {
+        NewInventory {
+            product_id,
+            stock,
+            price_cents: price.cents,
+            currency: price.currency,
+            net_weight: net_weight.amount,
+            net_weight_unit: net_weight.unit,
+            reorder_threshold: None,
+            batch_id: None,
+        }
+    }
+
+    pub fn with_batch_id(self, batch_id: i32) -> Self {
+        NewInventory {
+            batch_id: Some(batch_id),
+            ..self
+        }
+    }
+
+    pub fn with_reorder_threshold(self, reorder_threshold: i32) -> Self {
+        NewInventory {
+            reorder_threshold: Some(reorder_threshold),
+            ..self
+        }
+    }
+
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.non_negative("stock", self.stock);
+        if let Some(threshold) = self.reorder_threshold {
+            errors.non_negative("reorder_threshold", threshold);
+        }
+        errors.positive("price", self.price_cents);
+        errors.positive("net_weight", self.net_weight);
+        errors
+    }
+}
+
+/// Body of `PUT /inventories/{id}`. Unlike a new item, a replacement must
+/// give `reorder_threshold`, so leaving it out cannot silently reset it.
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub struct InventoryReplacement(pub NewInventory);
+
+impl InventoryReplacement {
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = self.0.check();
+        if self.0.reorder_threshold.is_none() {
+            errors.add(
+                "reorder_threshold",
+                "is required when replacing an inventory item.",
+            );
+        }
+        errors
+    }
+}
+
+#[derive(Debug, Default, Deserialize, AsChangeset)]
+#[table_name = "inventories"]
+pub struct InventoryChanges {
+    pub stock: Option<i32>,
+    #[serde(
+        rename = "price",
+        default,
+        deserialize_with = "deserialize_optional_price"
+    )]
+    pub price_cents: Option<i64>,
+    #[serde(default, deserialize_with = "deserialize_optional_currency")]
+    pub currency: Option<String>,
+    pub net_weight: Option<f32>,
+    pub net_weight_unit: Option<WeightUnit>,
+    pub reorder_threshold: Option<i32>,
+    /// `null` unlinks the batch; leaving the field out keeps it.
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub batch_id: Option<Option<i32>>,
+}
+
+impl InventoryChanges {
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        if let Some(stock) = self.stock {
+            errors.non_negative("stock", stock);
+        }
+        if let Some(threshold) = self.reorder_threshold {
+            errors.non_negative("reorder_threshold", threshold);
+        }
+        if let Some(price) = self.price_cents {
+            errors.positive("price", price);
+        }
+        if let Some(weight) = self.net_weight {
+            errors.positive("net_weight", weight);
+        }
+        errors
+    }
+}
+
+/// An inventory row. `price` is serialized as an exact decimal string next to
+/// its `currency`, e.g. `"price": "15.10", "currency": "USD"`.
+#[derive(Debug, Serialize, Identifiable, Associations, Queryable, QueryableByName)]
+#[belongs_to(Product)]
+#[table_name = "inventories"]
+pub struct Inventory {
+    id: i32,
+    product_id: i32,
+    stock: i32,
+    net_weight: f32,
+    #[serde(rename = "price", serialize_with = "serialize_price")]
+    price_cents: i64,
+    currency: String,
+    net_weight_unit: WeightUnit,
+    reorder_threshold: i32,
+    batch_id: Option<i32>,
+    recalled: bool,
+}
+impl Inventory {
+    pub fn get_id(&self) -> &i32 {
+        &self.id
+    }
+
+    pub fn get_stock(&self) -> &i32 {
+        &self.stock
+    }
+
+    pub fn get_product_id(&self) -> &i32 {
+        &self.product_id
+    }
+
+    pub fn get_batch_id(&self) -> Option<&i32> {
+        self.batch_id.as_ref()
+    }
+
+    pub fn is_recalled(&self) -> bool {
+        self.recalled
+    }
+
+    /// Rejects a `batch_id` that was packed for another product or has
+    /// already been recalled.
+    pub fn check_batch(&self, conn: &PgConnection) -> Result<(), diesel::result::Error> {
+        let batch_id = match self.batch_id {
+            Some(batch_id) => batch_id,
+            None => return Ok(()),
+        };
+        let (product_id, recalled_at) = batches::table
+            .inner_join(cannabis::table)
+            .filter(batches::id.eq(batch_id))
+            .select((cannabis::product_id, batches::recalled_at))
+            .get_result::<(i32, Option<NaiveDateTime>)>(conn)?;
+        if product_id != self.product_id {
+            return Err(bad_request(&format!(
+                "Batch {} is not a batch of product {}.",
+                batch_id, self.product_id
+            )));
+        }
+        if recalled_at.is_some() {
+            return Err(bad_request(&format!(
+                "Batch {} has been recalled.",
+                batch_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Locks inventory `inv_id` for an update and brings its stored price up
+    /// to the one in effect, so an update that leaves the price alone keeps a
+    /// scheduled change that has since come into effect.
+    pub fn lock_current(
+        conn: &PgConnection,
+        inv_id: &i32,
+    ) -> Result<Inventory, diesel::result::Error> {
+        let _stmt = "UPDATE inventories i
+                     SET price_cents = p.price_cents, currency = p.currency
+                     FROM priced_inventories p
+                     WHERE p.id = i.id AND i.id = $1
+                     RETURNING i.*";
+        sql_query(_stmt).bind::<Integer, _>(inv_id).get_result(conn)
+    }
+
+    /// Reads the stock of inventory `inv_id` and locks the row until the
+    /// surrounding transaction ends.
+    pub fn lock_stock(conn: &PgConnection, inv_id: &i32) -> Result<i32, diesel::result::Error> {
+        inventories::table
+            .find(inv_id)
+            .select(inventories::stock)
+            .for_update()
+            .get_result(conn)
+    }
+
+    pub fn get_price(&self) -> Money {
+        Money {
+            cents: self.price_cents,
+            currency: self.currency.clone(),
+        }
+    }
+
+    pub fn get_net_weight(&self) -> Weight {
+        Weight::new(self.net_weight, self.net_weight_unit)
+    }
+
+    pub fn with_product_id(
+        conn: &PgConnection,
+        prod_id: &i32,
+    ) -> Result<Vec<InventoryResponse>, diesel::result::Error> {
+        let _stmt = "SELECT
+                      i.id, i.product_id, p.name, p.category, i.stock,
+                      i.price_cents, i.currency, i.net_weight, i.net_weight_unit
+                    FROM priced_inventories i INNER JOIN products p ON i.product_id = p.id
+                    WHERE p.id = $1 AND NOT i.recalled AND NOT batch_expired(i.batch_id)";
+        sql_query(_stmt)
+            .bind::<Integer, _>(prod_id)
+            .get_results(conn)
+    }
+}
+
+impl Sortable for Inventory {
+    const SORT_KEYS: &'static [SortKey] = &[
+        SortKey {
+            name: "id",
+            sql_type: "int4",
+        },
+        SortKey {
+            name: "stock",
+            sql_type: "int4",
+        },
+        SortKey {
+            name: "price_cents",
+            sql_type: "int8",
+        },
+        SortKey {
+            name: "net_weight",
+            sql_type: "float4",
+        },
+    ];
+
+    fn cursor_id(&self) -> i32 {
+        self.id
+    }
+
+    fn sort_value(&self, key: &str) -> String {
+        match key {
+            "stock" => self.stock.to_string(),
+            "price_cents" => self.price_cents.to_string(),
+            "net_weight" => self.net_weight.to_string(),
+            _ => self.id.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct ProductExport {
+    #[sql_type = "Integer"]
+    id: i32,
+
+    #[sql_type = "VarChar"]
+    name: String,
+
+    #[sql_type = "CategoryMapping"]
+    category: Category,
+
+    #[sql_type = "Nullable<FamilyMapping>"]
+    family: Option<Family>,
+
+    #[sql_type = "Nullable<Float>"]
+    thc: Option<f32>,
+
+    #[sql_type = "Nullable<Float>"]
+    cbd: Option<f32>,
+
+    #[sql_type = "Nullable<Float>"]
+    total_cannabinoids: Option<f32>,
+}
+
+impl Sortable for ProductExport {
+    const SORT_KEYS: &'static [SortKey] = &[SortKey {
+        name: "id",
+        sql_type: "int4",
+    }];
+
+    fn cursor_id(&self) -> i32 {
+        self.id
+    }
+
+    fn sort_value(&self, _key: &str) -> String {
+        self.id.to_string()
+    }
+}
+
+/// An inventory row joined with its product. Serializes with a computed
+/// `price_per_gram` so clients never divide prices themselves.
+#[derive(Debug, QueryableByName)]
+pub struct InventoryResponse {
+    #[sql_type = "Integer"]
+    id: i32,
+
+    #[sql_type = "Integer"]
+    product_id: i32,
+
+    #[sql_type = "VarChar"]
+    name: String,
+
+    #[sql_type = "CategoryMapping"]
+    category: Category,
+
+    #[sql_type = "Integer"]
+    stock: i32,
+
+    #[sql_type = "BigInt"]
+    price_cents: i64,
+
+    #[sql_type = "VarChar"]
+    currency: String,
+
+    #[sql_type = "Float"]
+    net_weight: f32,
+
+    #[sql_type = "WeightUnitMapping"]
+    net_weight_unit: WeightUnit,
+}
+
+impl InventoryResponse {
+    pub fn get_price(&self) -> Money {
+        Money {
+            cents: self.price_cents,
+            currency: self.currency.clone(),
+        }
+    }
+
+    pub fn get_net_weight(&self) -> Weight {
+        Weight::new(self.net_weight, self.net_weight_unit)
+    }
+
+    /// Converts the net weight to `unit`, or leaves it as stored when `None`.
+    pub fn in_unit(self, unit: Option<WeightUnit>) -> Self {
+        let weight = match unit {
+            Some(unit) => self.get_net_weight().to(unit),
+            None => return self,
+        };
+        InventoryResponse {
+            net_weight: weight.amount,
+            net_weight_unit: weight.unit,
+            ..self
+        }
+    }
+}
+
+impl Serialize for InventoryResponse {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+
+        let per_gram = self.get_price().per_gram(&self.get_net_weight());
+        let mut row = serializer.serialize_struct("InventoryResponse", 10)?;
+        row.serialize_field("id", &self.id)?;
+        row.serialize_field("product_id", &self.product_id)?;
+        row.serialize_field("name", &self.name)?;
+        row.serialize_field("category", &self.category)?;
+        row.serialize_field("stock", &self.stock)?;
+        row.serialize_field("price", &format_cents(self.price_cents))?;
+        row.serialize_field("currency", &self.currency)?;
+        row.serialize_field("net_weight", &self.net_weight)?;
+        row.serialize_field("net_weight_unit", &self.net_weight_unit)?;
+        row.serialize_field("price_per_gram", &per_gram.map(|m| format_cents(m.cents)))?;
+        row.end()
+    }
+}
+
+impl Sortable for InventoryResponse {
+    const SORT_KEYS: &'static [SortKey] = &[
+        SortKey {
+            name: "id",
+            sql_type: "int4",
+        },
+        SortKey {
+            name: "name",
+            sql_type: "varchar",
+        },
+        SortKey {
+            name: "stock",
+            sql_type: "int4",
+        },
+        SortKey {
+            name: "price_cents",
+            sql_type: "int8",
+        },
+        SortKey {
+            name: "net_weight",
+            sql_type: "float4",
+        },
+    ];
+
+    fn cursor_id(&self) -> i32 {
+        self.id
+    }
+
+    fn sort_value(&self, key: &str) -> String {
+        match key {
+            "name" => self.name.to_string(),
+            "stock" => self.stock.to_string(),
+            "price_cents" => self.price_cents.to_string(),
+            "net_weight" => self.net_weight.to_string(),
+            _ => self.id.to_string(),
+        }
+    }
+}
+
+/// One line of the restock report: an inventory row at or below its
+/// `reorder_threshold`, joined with its product like `InventoryResponse`.
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct LowStockItem {
+    #[sql_type = "Integer"]
+    id: i32,
+
+    #[sql_type = "Integer"]
+    product_id: i32,
+
+    #[sql_type = "VarChar"]
+    name: String,
+
+    #[sql_type = "CategoryMapping"]
+    category: Category,
+
+    #[sql_type = "Integer"]
+    stock: i32,
+
+    #[sql_type = "Integer"]
+    reorder_threshold: i32,
+
+    #[sql_type = "Integer"]
+    shortfall: i32,
+}
+
+impl LowStockItem {
+    /// Every item at or below its threshold, furthest below first.
+    pub fn report(conn: &PgConnection) -> Result<Vec<LowStockItem>, diesel::result::Error> {
+        let _stmt = "SELECT i.id, i.product_id, p.name, p.category, i.stock,
+                      i.reorder_threshold, i.reorder_threshold - i.stock AS shortfall
+                    FROM inventories i INNER JOIN products p ON i.product_id = p.id
+                    WHERE i.stock <= i.reorder_threshold AND NOT i.recalled
+                    ORDER BY shortfall DESC, i.id";
+        sql_query(_stmt).load(conn)
+    }
+}
+
+sql_function! {
+    /// True once the batch's package date plus its category's shelf life has
+    /// passed. False for no batch or a category that never expires.
+    fn batch_expired(batch: Nullable<Integer>) -> Bool;
+}
+
+/// Days stock of `category` keeps after its batch was packaged.
+#[derive(Debug, Serialize, Queryable, Insertable)]
+#[table_name = "shelf_lives"]
+pub struct ShelfLife {
+    category: Category,
+    days: i32,
+}
+
+/// Body of `PUT /shelf-lives/{category}`.
+#[derive(Debug, Deserialize)]
+pub struct ShelfLifeInput {
+    pub days: i32,
+}
+
+impl ShelfLifeInput {
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.positive("days", self.days);
+        errors
+    }
+}
+
+impl ShelfLife {
+    pub fn list(conn: &PgConnection) -> Result<Vec<ShelfLife>, diesel::result::Error> {
+        shelf_lives::table.order(shelf_lives::category).load(conn)
+    }
+
+    pub fn set(
+        conn: &PgConnection,
+        category: Category,
+        input: ShelfLifeInput,
+    ) -> Result<ShelfLife, diesel::result::Error> {
+        let days = input.days;
+        diesel::insert_into(shelf_lives::table)
+            .values(&ShelfLife { category, days })
+            .on_conflict(shelf_lives::category)
+            .do_update()
+            .set(shelf_lives::days.eq(days))
+            .get_result(conn)
+    }
+
+    /// Removes the shelf life of `category`, so its stock no longer expires.
+    pub fn clear(
+        conn: &PgConnection,
+        category: &Category,
+    ) -> Result<ShelfLife, diesel::result::Error> {
+        diesel::delete(shelf_lives::table.find(category)).get_result(conn)
+    }
+}
+
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct BatchExpiration {
+    #[sql_type = "Integer"]
+    batch_id: i32,
+
+    #[sql_type = "Date"]
+    package_date: NaiveDate,
+
+    #[sql_type = "Nullable<Date>"]
+    expires_on: Option<NaiveDate>,
+
+    #[sql_type = "Nullable<Integer>"]
+    days_remaining: Option<i32>,
+}
+
+impl Batch {
+    /// When the batch expires, or `null` if its category never does.
+    pub fn expiration(
+        conn: &PgConnection,
+        _id: &i32,
+    ) -> Result<BatchExpiration, diesel::result::Error> {
+        let _stmt = "SELECT b.id AS batch_id, b.package_date, e.expires_on,
+                      e.expires_on - CURRENT_DATE AS days_remaining
+                     FROM batches b
+                     CROSS JOIN LATERAL (SELECT batch_expires_on(b.id) AS expires_on) e
+                     WHERE b.id = $1";
+        sql_query(_stmt).bind::<Integer, _>(_id).get_result(conn)
+    }
+}
+
+/// An inventory row whose batch expires soon, joined with its product like
+/// `InventoryResponse`. `days_remaining` is negative once it has expired.
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct ExpiringInventory {
+    #[sql_type = "Integer"]
+    id: i32,
+
+    #[sql_type = "Integer"]
+    product_id: i32,
+
+    #[sql_type = "VarChar"]
+    name: String,
+
+    #[sql_type = "CategoryMapping"]
+    category: Category,
+
+    #[sql_type = "Integer"]
+    batch_id: i32,
+
+    #[sql_type = "Integer"]
+    stock: i32,
+
+    #[sql_type = "Date"]
+    expires_on: NaiveDate,
+
+    #[sql_type = "Integer"]
+    days_remaining: i32,
+}
+
+impl ExpiringInventory {
+    /// Stocked, unrecalled rows expiring in `days` days or fewer, soonest
+    /// first. Stock that has already expired is listed too so it can be
+    /// pulled.
+    pub fn within(
+        conn: &PgConnection,
+        days: i32,
+    ) -> Result<Vec<ExpiringInventory>, diesel::result::Error> {
+        let _stmt = "SELECT i.id, i.product_id, p.name, p.category, i.batch_id, i.stock,
+                      e.expires_on, e.expires_on - CURRENT_DATE AS days_remaining
+                     FROM inventories i
+                     INNER JOIN products p ON i.product_id = p.id
+                     CROSS JOIN LATERAL (SELECT batch_expires_on(i.batch_id) AS expires_on) e
+                     WHERE e.expires_on <= CURRENT_DATE + $1
+                       AND i.stock > 0 AND NOT i.recalled
+                     ORDER BY e.expires_on, i.id";
+        sql_query(_stmt).bind::<Integer, _>(days).load(conn)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, DbEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MovementKind {
+    Receipt,
+    Sale,
+    Adjustment,
+    Waste,
+    Transfer,
+}
+
+impl Field<'static, MovementKind> for MovementKind {
+    fn fields() -> Vec<&'static str> {
+        vec!["receipt", "sale", "adjustment", "waste", "transfer"]
+    }
+}
+
+/// Body of `POST /inventories/{id}/movements`. `quantity` is the signed change
+/// in stock: positive for receipts, negative for sales and waste, either way
+/// for adjustments and transfers. `user_id` is the acting user's id in the
+/// users service.
+#[derive(Debug, Deserialize)]
+pub struct MovementInput {
+    pub kind: MovementKind,
+    pub quantity: i32,
+    pub reason: String,
+    pub user_id: i32,
+}
+
+impl MovementInput {
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.required("reason", &self.reason);
+        match (self.kind, self.quantity) {
+            (MovementKind::Receipt, q) if q <= 0 => {
+                errors.add("quantity", "must be positive for a receipt.")
+            }
+            (MovementKind::Sale, q) | (MovementKind::Waste, q) if q >= 0 => {
+                errors.add("quantity", "must be negative for a sale or waste.")
+            }
+            (_, 0) => errors.add("quantity", "must not be zero."),
+            _ => (),
+        }
+        errors
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "stock_movements"]
+struct NewStockMovement<'a> {
+    inventory_id: i32,
+    kind: MovementKind,
+    quantity: i32,
+    balance: i32,
+    reason: &'a str,
+    user_id: Option<i32>,
+}
+
+/// One entry of the append-only stock ledger. `balance` is the inventory's
+/// stock right after this movement.
+#[derive(Debug, Serialize, Identifiable, Associations, Queryable, QueryableByName)]
+#[belongs_to(Inventory)]
+#[table_name = "stock_movements"]
+pub struct StockMovement {
+    id: i32,
+    inventory_id: i32,
+    kind: MovementKind,
+    quantity: i32,
+    balance: i32,
+    reason: String,
+    user_id: Option<i32>,
+    created_at: NaiveDateTime,
+}
+
+#[derive(Debug)]
+pub enum StockError {
+    Insufficient {
+        inventory_id: i32,
+        available: i32,
+        requested: i32,
+    },
+    Db(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for StockError {
+    fn from(e: diesel::result::Error) -> Self {
+        StockError::Db(e)
+    }
+}
+
+impl StockMovement {
+    pub fn get_kind(&self) -> &MovementKind {
+        &self.kind
+    }
+
+    pub fn get_quantity(&self) -> &i32 {
+        &self.quantity
+    }
+
+    pub fn get_balance(&self) -> &i32 {
+        &self.balance
+    }
+
+    /// Applies `input` to inventory `inv_id`. The inventory row is locked, the
+    /// new stock may not drop below zero, and `inventories.stock` is updated
+    /// in the same transaction that appends the movement.
+    pub fn record(
+        conn: &PgConnection,
+        inv_id: &i32,
+        input: MovementInput,
+    ) -> Result<StockMovement, StockError> {
+        conn.transaction(|| {
+            let stock = Inventory::lock_stock(conn, inv_id)?;
+            let balance = stock
+                .checked_add(input.quantity)
+                .filter(|b| *b >= 0)
+                .ok_or(StockError::Insufficient {
+                    inventory_id: *inv_id,
+                    available: stock,
+                    requested: -input.quantity,
+                })?;
+            diesel::update(inventories::table.find(inv_id))
+                .set(inventories::stock.eq(balance))
+                .execute(conn)?;
+
+            let new = NewStockMovement {
+                inventory_id: *inv_id,
+                kind: input.kind,
+                quantity: input.quantity,
+                balance,
+                reason: &input.reason,
+                user_id: Some(input.user_id),
+            };
+            Ok(diesel::insert_into(stock_movements::table)
+                .values(&new)
+                .get_result(conn)?)
+        })
+    }
+
+    /// Appends a system movement for a stock change made outside `record`,
+    /// such as creating an inventory row or setting `stock` directly. Must run
+    /// in the transaction that changed the stock.
+    pub fn reconcile(
+        conn: &PgConnection,
+        inv_id: &i32,
+        before: i32,
+        after: i32,
+        reason: &str,
+    ) -> Result<(), diesel::result::Error> {
+        if before == after {
+            return Ok(());
+        }
+        let kind = match before {
+            0 if after > 0 => MovementKind::Receipt,
+            _ => MovementKind::Adjustment,
+        };
+        let new = NewStockMovement {
+            inventory_id: *inv_id,
+            kind,
+            quantity: after - before,
+            balance: after,
+            reason,
+            user_id: None,
+        };
+        diesel::insert_into(stock_movements::table)
+            .values(&new)
+            .execute(conn)
+            .map(|_| ())
+    }
+
+    /// Movements of inventory `inv_id`, oldest first unless sorted otherwise.
+    pub fn of_inventory(
+        conn: &PgConnection,
+        inv_id: &i32,
+        params: &PageParams,
+    ) -> Result<Page<StockMovement>, diesel::result::Error> {
+        let _stmt = "SELECT * FROM stock_movements WHERE inventory_id = $4";
+        load_page_with::<_, Integer, _>(conn, _stmt, inv_id, params)
+    }
+}
+
+impl Sortable for StockMovement {
+    const SORT_KEYS: &'static [SortKey] = &[
+        SortKey {
+            name: "id",
+            sql_type: "int4",
+        },
+        SortKey {
+            name: "created_at",
+            sql_type: "timestamp",
+        },
+    ];
+
+    fn cursor_id(&self) -> i32 {
+        self.id
+    }
+
+    fn sort_value(&self, key: &str) -> String {
+        match key {
+            "created_at" => self.created_at.to_string(),
+            _ => self.id.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, DbEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderStatus {
+    Pending,
+    Paid,
+    Fulfilled,
+    Cancelled,
+}
+
+impl OrderStatus {
+    /// Orders move pending -> paid -> fulfilled and can be cancelled until
+    /// they are fulfilled.
+    pub fn can_become(&self, next: &OrderStatus) -> bool {
+        matches!(
+            (self, next),
+            (OrderStatus::Pending, OrderStatus::Paid)
+                | (OrderStatus::Pending, OrderStatus::Cancelled)
+                | (OrderStatus::Paid, OrderStatus::Fulfilled)
+                | (OrderStatus::Paid, OrderStatus::Cancelled)
+        )
+    }
+}
+
+impl Field<'static, OrderStatus> for OrderStatus {
+    fn fields() -> Vec<&'static str> {
+        vec!["pending", "paid", "fulfilled", "cancelled"]
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckoutLine {
+    pub inventory_id: i32,
+    pub quantity: i32,
+}
+
+/// Body of `POST /checkout`. `user_id` is the customer's id in the users
+/// service.
+#[derive(Debug, Deserialize)]
+pub struct CheckoutInput {
+    pub user_id: i32,
+    pub lines: Vec<CheckoutLine>,
+}
+
+impl CheckoutInput {
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        if self.lines.is_empty() {
+            errors.add("lines", "must contain at least one item.");
+        }
+        if self.lines.iter().any(|l| l.quantity <= 0) {
+            errors.add("quantity", "must be greater than zero.");
+        }
+        let mut ids = self
+            .lines
+            .iter()
+            .map(|l| l.inventory_id)
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        if ids.windows(2).any(|w| w[0] == w[1]) {
+            errors.add("inventory_id", "must not appear on more than one line.");
+        }
+        errors
+    }
+}
+
+/// Body of `PATCH /orders/{id}`. `user_id` is recorded on the stock movements
+/// a cancellation makes.
+#[derive(Debug, Deserialize)]
+pub struct OrderStatusInput {
+    pub status: OrderStatus,
+    pub user_id: i32,
+}
+
+/// An inventory row locked for checkout, priced at the price in effect.
+#[derive(Debug, QueryableByName)]
+struct LockedInventory {
+    #[sql_type = "Integer"]
+    id: i32,
+
+    #[sql_type = "BigInt"]
+    price_cents: i64,
+
+    #[sql_type = "VarChar"]
+    currency: String,
+
+    #[sql_type = "Bool"]
+    recalled: bool,
+
+    #[sql_type = "Bool"]
+    expired: bool,
+}
+
+#[derive(Debug)]
+pub enum OrderError {
+    Stock(StockError),
+    Invalid(String),
+    Transition { from: OrderStatus, to: OrderStatus },
+    Recalled { inventory_id: i32 },
+    Expired { inventory_id: i32 },
+}
+
+impl From<StockError> for OrderError {
+    fn from(e: StockError) -> Self {
+        OrderError::Stock(e)
+    }
+}
+
+impl From<diesel::result::Error> for OrderError {
+    fn from(e: diesel::result::Error) -> Self {
+        OrderError::Stock(StockError::Db(e))
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "orders"]
+struct NewOrder<'a> {
+    user_id: i32,
+    status: OrderStatus,
+    total_cents: i64,
+    currency: &'a str,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "order_lines"]
+struct NewOrderLine {
+    order_id: i32,
+    inventory_id: i32,
+    quantity: i32,
+    unit_price_cents: i64,
+    line_total_cents: i64,
+}
+
+#[derive(Debug, Serialize, Identifiable, Queryable, QueryableByName)]
+#[table_name = "orders"]
+pub struct Order {
+    id: i32,
+    user_id: i32,
+    status: OrderStatus,
+    #[serde(rename = "total", serialize_with = "serialize_price")]
+    total_cents: i64,
+    currency: String,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Identifiable, Associations, Queryable, QueryableByName)]
+#[belongs_to(Order)]
+#[table_name = "order_lines"]
+pub struct OrderLine {
+    id: i32,
+    order_id: i32,
+    inventory_id: i32,
+    quantity: i32,
+    #[serde(rename = "unit_price", serialize_with = "serialize_price")]
+    unit_price_cents: i64,
+    #[serde(rename = "line_total", serialize_with = "serialize_price")]
+    line_total_cents: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderDetail {
+    #[serde(flatten)]
+    order: Order,
+    lines: Vec<OrderLine>,
+}
+
+impl Order {
+    pub fn get_id(&self) -> &i32 {
+        &self.id
+    }
+
+    pub fn get_status(&self) -> &OrderStatus {
+        &self.status
+    }
+
+    pub fn get_total(&self) -> Money {
+        Money {
+            cents: self.total_cents,
+            currency: self.currency.clone(),
+        }
+    }
+
+    /// Sells `input` in one transaction. Every inventory row on the order is
+    /// locked in id order, so concurrent checkouts queue up instead of both
+    /// seeing the same stock, and a line that would oversell rolls back the
+    /// whole order. Lines are priced at the price in effect now, totals are
+    /// summed in cents and every line must share one currency.
+    pub fn checkout(conn: &PgConnection, input: CheckoutInput) -> Result<OrderDetail, OrderError> {
+        conn.transaction(|| {
+            let ids = input
+                .lines
+                .iter()
+                .map(|l| l.inventory_id)
+                .collect::<Vec<_>>();
+            let _stmt = "SELECT p.id, p.price_cents, p.currency, p.recalled,
+                           batch_expired(p.batch_id) AS expired
+                         FROM inventories i INNER JOIN priced_inventories p ON p.id = i.id
+                         WHERE i.id = ANY($1)
+                         ORDER BY i.id
+                         FOR UPDATE OF i";
+            let locked: Vec<LockedInventory> = sql_query(_stmt)
+                .bind::<Array<Integer>, _>(&ids)
+                .load(conn)?;
+
+            let mut priced = Vec::with_capacity(input.lines.len());
+            let mut total: Option<Money> = None;
+            for line in &input.lines {
+                let inv = locked
+                    .iter()
+                    .find(|inv| inv.id == line.inventory_id)
+                    .ok_or(diesel::result::Error::NotFound)?;
+                if inv.recalled {
+                    return Err(OrderError::Recalled {
+                        inventory_id: inv.id,
+                    });
+                }
+                if inv.expired {
+                    return Err(OrderError::Expired {
+                        inventory_id: inv.id,
+                    });
+                }
+                let unit = Money {
+                    cents: inv.price_cents,
+                    currency: inv.currency.clone(),
+                };
+                let line_total = unit
+                    .checked_mul(i64::from(line.quantity))
+                    .map_err(OrderError::Invalid)?;
+                total = Some(match total {
+                    Some(total) => total
+                        .checked_add(&line_total)
+                        .map_err(OrderError::Invalid)?,
+                    None => line_total.clone(),
+                });
+                priced.push((line, unit, line_total));
+            }
+            let total = total.ok_or_else(|| OrderError::Invalid("Order is empty.".to_owned()))?;
+
+            let order: Order = diesel::insert_into(orders::table)
+                .values(&NewOrder {
+                    user_id: input.user_id,
+                    status: OrderStatus::Pending,
+                    total_cents: total.cents,
+                    currency: &total.currency,
+                })
+                .get_result(conn)?;
+
+            let mut lines = Vec::with_capacity(priced.len());
+            for (line, unit, line_total) in priced {
+                let sale = MovementInput {
+                    kind: MovementKind::Sale,
+                    quantity: -line.quantity,
+                    reason: format!("Order #{}", order.id),
+                    user_id: input.user_id,
+                };
+                StockMovement::record(conn, &line.inventory_id, sale)?;
+                lines.push(
+                    diesel::insert_into(order_lines::table)
+                        .values(&NewOrderLine {
+                            order_id: order.id,
+                            inventory_id: line.inventory_id,
+                            quantity: line.quantity,
+                            unit_price_cents: unit.cents,
+                            line_total_cents: line_total.cents,
+                        })
+                        .get_result(conn)?,
+                );
+            }
+
+            Ok(OrderDetail { order, lines })
+        })
+    }
+
+    pub fn detail(conn: &PgConnection, ord_id: &i32) -> Result<OrderDetail, diesel::result::Error> {
+        let order: Order = orders::table.find(ord_id).get_result(conn)?;
+        let lines = OrderLine::belonging_to(&order)
+            .order(order_lines::id)
+            .load(conn)?;
+        Ok(OrderDetail { order, lines })
+    }
+
+    /// Moves order `ord_id` to `input.status`. Cancelling puts every line's
+    /// quantity back into stock through the movement ledger.
+    pub fn transition(
+        conn: &PgConnection,
+        ord_id: &i32,
+        input: OrderStatusInput,
+    ) -> Result<Order, OrderError> {
+        conn.transaction(|| {
+            let order: Order = orders::table.find(ord_id).for_update().get_result(conn)?;
+            if !order.status.can_become(&input.status) {
+                return Err(OrderError::Transition {
+                    from: order.status,
+                    to: input.status,
+                });
+            }
+
+            if input.status == OrderStatus::Cancelled {
+                let lines: Vec<OrderLine> = OrderLine::belonging_to(&order)
+                    .order(order_lines::inventory_id)
+                    .load(conn)?;
+                for line in lines {
+                    let restock = MovementInput {
+                        kind: MovementKind::Adjustment,
+                        quantity: line.quantity,
+                        reason: format!("Order #{} cancelled", order.id),
+                        user_id: input.user_id,
+                    };
+                    StockMovement::record(conn, &line.inventory_id, restock)?;
+                }
+            }
+
+            Ok(diesel::update(orders::table.find(ord_id))
+                .set((
+                    orders::status.eq(input.status),
+                    orders::updated_at.eq(diesel::dsl::now),
+                ))
+                .get_result(conn)?)
+        })
+    }
+}
+
+impl Sortable for Order {
+    const SORT_KEYS: &'static [SortKey] = &[
+        SortKey {
+            name: "id",
+            sql_type: "int4",
+        },
+        SortKey {
+            name: "created_at",
+            sql_type: "timestamp",
+        },
+    ];
+
+    fn cursor_id(&self) -> i32 {
+        self.id
+    }
+
+    fn sort_value(&self, key: &str) -> String {
+        match key {
+            "created_at" => self.created_at.to_string(),
+            _ => self.id.to_string(),
+        }
+    }
+}
+
+/// Body of `POST /inventories/{id}/prices`. Without `effective_from` the
+/// price applies immediately.
+#[derive(Debug, Deserialize)]
+pub struct PriceInput {
+    #[serde(rename = "price", deserialize_with = "deserialize_price")]
+    pub price_cents: i64,
+    #[serde(
+        default = "default_currency",
+        deserialize_with = "deserialize_currency"
+    )]
+    pub currency: String,
+    pub effective_from: Option<NaiveDateTime>,
+}
+
+impl PriceInput {
+    pub fn check(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.positive("price", self.price_cents);
+        errors
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "price_history"]
+struct NewPriceChange<'a> {
+    inventory_id: i32,
+    product_id: i32,
+    price_cents: i64,
+    currency: &'a str,
+    effective_from: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Identifiable, Associations, Queryable, QueryableByName)]
+#[belongs_to(Inventory)]
+#[table_name = "price_history"]
+pub struct PriceChange {
+    id: i32,
+    inventory_id: i32,
+    product_id: i32,
+    #[serde(rename = "price", serialize_with = "serialize_price")]
+    price_cents: i64,
+    currency: String,
+    effective_from: NaiveDateTime,
+    created_at: NaiveDateTime,
+}
+
+/// One span of an inventory item's price timeline. `effective_to` is `None`
+/// for the last known price.
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct PricePoint {
+    #[sql_type = "Integer"]
+    id: i32,
+
+    #[sql_type = "BigInt"]
+    #[serde(rename = "price", serialize_with = "serialize_price")]
+    price_cents: i64,
+
+    #[sql_type = "VarChar"]
+    currency: String,
+
+    #[sql_type = "Timestamp"]
+    effective_from: NaiveDateTime,
+
+    #[sql_type = "Nullable<Timestamp>"]
+    effective_to: Option<NaiveDateTime>,
+
+    #[sql_type = "Bool"]
+    current: bool,
+
+    #[sql_type = "Bool"]
+    scheduled: bool,
+}
+
+impl PriceChange {
+    fn now(conn: &PgConnection) -> Result<NaiveDateTime, diesel::result::Error> {
+        diesel::select(diesel::dsl::now).get_result(conn)
+    }
+
+    /// Records `inv`'s current price as taking effect now. Must run in the
+    /// transaction that set the price.
+    pub fn record_current(
+        conn: &PgConnection,
+        inv: &Inventory,
+    ) -> Result<(), diesel::result::Error> {
+        let new = NewPriceChange {
+            inventory_id: inv.id,
+            product_id: inv.product_id,
+            price_cents: inv.price_cents,
+            currency: &inv.currency,
+            effective_from: PriceChange::now(conn)?,
+        };
+        diesel::insert_into(price_history::table)
+            .values(&new)
+            .execute(conn)
+            .map(|_| ())
+    }
+
+    /// Schedules `input` for inventory `inv_id`. Changes cannot be backdated,
+    /// so the timeline only ever grows forward.
+    pub fn schedule(
+        conn: &PgConnection,
+        inv_id: &i32,
+        input: PriceInput,
+    ) -> Result<PriceChange, diesel::result::Error> {
+        conn.transaction(|| {
+            let product_id = inventories::table
+                .find(inv_id)
+                .select(inventories::product_id)
+                .for_update()
+                .get_result(conn)?;
+            let now = PriceChange::now(conn)?;
+            let effective_from = input.effective_from.unwrap_or(now);
+            if effective_from < now {
+                return Err(bad_request("`effective_from` must not be in the past."));
+            }
+
+            let change = diesel::insert_into(price_history::table)
+                .values(&NewPriceChange {
+                    inventory_id: *inv_id,
+                    product_id,
+                    price_cents: input.price_cents,
+                    currency: &input.currency,
+                    effective_from,
+                })
+                .get_result(conn)?;
+            Ok(change)
+        })
+    }
+
+    /// The full price timeline of inventory `inv_id`, oldest first. It is
+    /// still there after the item is deleted.
+    pub fn timeline(
+        conn: &PgConnection,
+        inv_id: &i32,
+    ) -> Result<Vec<PricePoint>, diesel::result::Error> {
+        let _stmt = "SELECT id, price_cents, currency, effective_from, effective_to,
+                       effective_from <= NOW() AND (effective_to IS NULL OR effective_to > NOW())
+                         AS current,
+                       effective_from > NOW() AS scheduled
+                     FROM (
+                        SELECT *, LEAD(effective_from) OVER (
+                          ORDER BY effective_from, id
+                        ) AS effective_to
+                        FROM price_history WHERE inventory_id = $1
+                     ) timeline
+                     ORDER BY effective_from, id";
+        sql_query(_stmt).bind::<Integer, _>(inv_id).load(conn)
+    }
 }
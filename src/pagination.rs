@@ -0,0 +1,272 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::pg::{Pg, PgConnection};
+use diesel::query_builder::QueryId;
+use diesel::query_source::QueryableByName;
+use diesel::result::Error;
+use diesel::serialize::ToSql;
+use diesel::sql_types::{BigInt, HasSqlType, Integer, Nullable, Text};
+use diesel::{sql_query, RunQueryDsl};
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 500;
+
+/// Query-string parameters accepted by every list endpoint.
+///
+/// `sort` names one of the model's `SORT_KEYS`, optionally prefixed with `-`
+/// for descending order. `cursor` is the `next_cursor` of a previous page and
+/// is only valid together with the `sort` it was issued for.
+#[derive(Debug, Default, Deserialize)]
+pub struct PageParams {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub sort: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    data: Vec<T>,
+    next_cursor: Option<String>,
+}
+
+impl<T: Sortable> Page<T> {
+    /// Builds a page from up to `limit + 1` rows loaded in `sort` order. The
+    /// extra row, if present, only signals that a next page exists.
+    pub fn from_rows(mut data: Vec<T>, limit: i64, sort: &Sort) -> Self {
+        let next_cursor = match data.len() as i64 > limit {
+            true => {
+                data.truncate(limit as usize);
+                data.last().map(|last| {
+                    encode_cursor(sort, last.cursor_id(), &last.sort_value(sort.key.name))
+                })
+            }
+            false => None,
+        };
+
+        Page { data, next_cursor }
+    }
+}
+
+impl<T> Page<T> {
+    pub fn get_data(&self) -> &Vec<T> {
+        &self.data
+    }
+
+    pub fn get_next_cursor(&self) -> &Option<String> {
+        &self.next_cursor
+    }
+
+    /// Transforms each row while keeping the cursor.
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            data: self.data.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+/// A column a list can be sorted by, and the Postgres type its cursor value
+/// is cast back to.
+pub struct SortKey {
+    pub name: &'static str,
+    pub sql_type: &'static str,
+}
+
+impl SortKey {
+    /// Whether `value` can be cast to `sql_type`. Cursors are checked here so
+    /// that a tampered one is rejected before it reaches Postgres.
+    fn accepts(&self, value: &str) -> bool {
+        match self.sql_type {
+            "int4" => value.parse::<i32>().is_ok(),
+            "int8" => value.parse::<i64>().is_ok(),
+            "float4" => matches!(value.parse::<f32>(), Ok(v) if v.is_finite()),
+            "date" => value.parse::<NaiveDate>().is_ok(),
+            "timestamp" => NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f").is_ok(),
+            "varchar" | "text" => true,
+            _ => false,
+        }
+    }
+}
+
+pub trait Sortable {
+    /// Sortable columns. The first one is used when no `sort` is given.
+    const SORT_KEYS: &'static [SortKey];
+
+    fn cursor_id(&self) -> i32;
+    fn sort_value(&self, key: &str) -> String;
+}
+
+pub struct Sort {
+    pub key: &'static SortKey,
+    pub desc: bool,
+}
+
+impl Sort {
+    fn spec(&self) -> String {
+        match self.desc {
+            true => format!("-{}", self.key.name),
+            false => self.key.name.to_owned(),
+        }
+    }
+}
+
+pub struct Cursor {
+    pub id: i32,
+    pub value: String,
+}
+
+pub fn bad_request(msg: &str) -> Error {
+    Error::QueryBuilderError(msg.to_owned().into())
+}
+
+impl PageParams {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    pub fn sort<T: Sortable>(&self) -> Result<Sort, Error> {
+        let spec = self.sort.as_deref().unwrap_or(T::SORT_KEYS[0].name);
+        let (name, desc) = match spec.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (spec, false),
+        };
+        T::SORT_KEYS
+            .iter()
+            .find(|k| k.name == name)
+            .map(|key| Sort { key, desc })
+            .ok_or_else(|| bad_request(&format!("Cannot sort by `{}`.", name)))
+    }
+
+    pub fn cursor(&self, sort: &Sort) -> Result<Option<Cursor>, Error> {
+        let raw = match &self.cursor {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+        let decoded = decode_hex(raw).ok_or_else(|| bad_request("Invalid cursor."))?;
+        let mut parts = decoded.splitn(3, '\n');
+        match (parts.next(), parts.next().map(str::parse), parts.next()) {
+            (Some(spec), Some(Ok(id)), Some(value))
+                if spec == sort.spec() && sort.key.accepts(value) =>
+            {
+                Ok(Some(Cursor {
+                    id,
+                    value: value.to_owned(),
+                }))
+            }
+            (Some(spec), Some(Ok(_)), Some(_)) if spec != sort.spec() => {
+                Err(bad_request("Cursor was issued for a different sort."))
+            }
+            _ => Err(bad_request("Invalid cursor.")),
+        }
+    }
+}
+
+fn encode_cursor(sort: &Sort, id: i32, value: &str) -> String {
+    format!("{}\n{}\n{}", sort.spec(), id, value)
+        .bytes()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn decode_hex(raw: &str) -> Option<String> {
+    let bytes = (0..raw.len())
+        .step_by(2)
+        .map(|i| {
+            raw.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// `select` wrapped for keyset pagination on the requested sort key, with
+/// `id` as the tie-breaker. The cursor value, cursor id and row limit are
+/// bound as `$1`, `$2` and `$3`.
+struct PageQuery {
+    sort: Sort,
+    limit: i64,
+    sql: String,
+    value: Option<String>,
+    id: Option<i32>,
+}
+
+impl PageQuery {
+    fn new<T: Sortable>(select: &str, params: &PageParams) -> Result<PageQuery, Error> {
+        let sort = params.sort::<T>()?;
+        let cursor = params.cursor(&sort)?;
+        let (dir, op) = match sort.desc {
+            true => ("DESC", "<"),
+            false => ("ASC", ">"),
+        };
+        let sql = format!(
+            "SELECT * FROM ({select}) AS page
+             WHERE $1::text IS NULL OR (page.{col}, page.id) {op} ($1::{ty}, $2)
+             ORDER BY page.{col} {dir}, page.id {dir}
+             LIMIT $3",
+            select = select,
+            col = sort.key.name,
+            ty = sort.key.sql_type,
+            op = op,
+            dir = dir,
+        );
+        let (value, id) = match cursor {
+            Some(c) => (Some(c.value), Some(c.id)),
+            None => (None, None),
+        };
+
+        Ok(PageQuery {
+            sort,
+            limit: params.limit(),
+            sql,
+            value,
+            id,
+        })
+    }
+}
+
+/// Loads one page of `select` using keyset pagination on the requested sort
+/// key, with `id` as the tie-breaker. `select` must produce an `id` column and
+/// every column named in `T::SORT_KEYS`.
+pub fn load_page<T>(
+    conn: &PgConnection,
+    select: &str,
+    params: &PageParams,
+) -> Result<Page<T>, Error>
+where
+    T: Sortable + QueryableByName<Pg>,
+{
+    let query = PageQuery::new::<T>(select, params)?;
+    let data = sql_query(&query.sql)
+        .bind::<Nullable<Text>, _>(query.value)
+        .bind::<Nullable<Integer>, _>(query.id)
+        .bind::<BigInt, _>(query.limit + 1)
+        .load(conn)?;
+
+    Ok(Page::from_rows(data, query.limit, &query.sort))
+}
+
+/// Like `load_page`, for a `select` that filters on one more value: `value`
+/// is bound as `$4` with SQL type `ST`.
+pub fn load_page_with<T, ST, U>(
+    conn: &PgConnection,
+    select: &str,
+    value: U,
+    params: &PageParams,
+) -> Result<Page<T>, Error>
+where
+    T: Sortable + QueryableByName<Pg>,
+    ST: QueryId,
+    Pg: HasSqlType<ST>,
+    U: ToSql<ST, Pg>,
+{
+    let query = PageQuery::new::<T>(select, params)?;
+    let data = sql_query(&query.sql)
+        .bind::<Nullable<Text>, _>(query.value)
+        .bind::<Nullable<Integer>, _>(query.id)
+        .bind::<BigInt, _>(query.limit + 1)
+        .bind::<ST, _>(value)
+        .load(conn)?;
+
+    Ok(Page::from_rows(data, query.limit, &query.sort))
+}
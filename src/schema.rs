@@ -7,6 +7,8 @@ table! {
         harvest_date -> Date,
         package_date -> Date,
         final_test_date -> Date,
+        recalled_at -> Nullable<Timestamp>,
+        recall_reason -> Nullable<Text>,
     }
 }
 
@@ -27,12 +29,96 @@ table! {
 table! {
     use diesel::sql_types::*;
 
+    cannabis_parents (child_id, parent_id) {
+        child_id -> Int4,
+        parent_id -> Int4,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::exports::*;
+
     inventories (id) {
         id -> Int4,
         product_id -> Int4,
         stock -> Int4,
-        price -> Float4,
         net_weight -> Float4,
+        price_cents -> Int8,
+        currency -> Varchar,
+        net_weight_unit -> WeightUnit,
+        reorder_threshold -> Int4,
+        batch_id -> Nullable<Int4>,
+        recalled -> Bool,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::exports::*;
+
+    lab_results (id) {
+        id -> Int4,
+        batch_id -> Int4,
+        lab_name -> Varchar,
+        tested_on -> Date,
+        thc -> Float4,
+        cbd -> Float4,
+        total_cannabinoids -> Float4,
+        myrcene -> Float4,
+        pinene -> Float4,
+        limonene -> Float4,
+        caryophyllene -> Float4,
+        terpinolene -> Float4,
+        pesticides -> TestOutcome,
+        heavy_metals -> TestOutcome,
+        microbials -> TestOutcome,
+        residual_solvents -> TestOutcome,
+        coa_path -> Nullable<Text>,
+        coa_content_type -> Nullable<Varchar>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    order_lines (id) {
+        id -> Int4,
+        order_id -> Int4,
+        inventory_id -> Int4,
+        quantity -> Int4,
+        unit_price_cents -> Int8,
+        line_total_cents -> Int8,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::exports::*;
+
+    orders (id) {
+        id -> Int4,
+        user_id -> Int4,
+        status -> OrderStatus,
+        total_cents -> Int8,
+        currency -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    price_history (id) {
+        id -> Int4,
+        inventory_id -> Int4,
+        product_id -> Int4,
+        price_cents -> Int8,
+        currency -> Varchar,
+        effective_from -> Timestamp,
+        created_at -> Timestamp,
     }
 }
 
@@ -47,6 +133,32 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::*;
+    use crate::exports::*;
+
+    shelf_lives (category) {
+        category -> Category,
+        days -> Int4,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::exports::*;
+
+    stock_movements (id) {
+        id -> Int4,
+        inventory_id -> Int4,
+        kind -> MovementKind,
+        quantity -> Int4,
+        balance -> Int4,
+        reason -> Text,
+        user_id -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
 
@@ -63,7 +175,24 @@ table! {
 
 joinable!(batches -> cannabis (cannabis_id));
 joinable!(cannabis -> products (product_id));
+joinable!(inventories -> batches (batch_id));
 joinable!(inventories -> products (product_id));
+joinable!(lab_results -> batches (batch_id));
+joinable!(order_lines -> inventories (inventory_id));
+joinable!(order_lines -> orders (order_id));
 joinable!(terpenes -> cannabis (cannabis_id));
 
-allow_tables_to_appear_in_same_query!(batches, cannabis, inventories, products, terpenes,);
+allow_tables_to_appear_in_same_query!(
+    batches,
+    cannabis,
+    cannabis_parents,
+    inventories,
+    lab_results,
+    order_lines,
+    orders,
+    price_history,
+    products,
+    shelf_lives,
+    stock_movements,
+    terpenes,
+);
@@ -0,0 +1,68 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where COA documents are kept when `COA_STORAGE_DIR` is not set.
+pub const DEFAULT_COA_DIR: &str = "storage/coa";
+
+/// Largest COA document accepted, in bytes.
+pub const MAX_COA_BYTES: usize = 10 * 1024 * 1024;
+
+/// Media types a COA can be uploaded as, with the extension it is saved under.
+const COA_TYPES: &[(&str, &str)] = &[
+    ("application/pdf", "pdf"),
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+];
+
+pub fn coa_extension(content_type: &str) -> Option<&'static str> {
+    COA_TYPES
+        .iter()
+        .find(|(media_type, _)| *media_type == content_type)
+        .map(|(_, extension)| *extension)
+}
+
+pub fn coa_dir() -> PathBuf {
+    env::var("COA_STORAGE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(DEFAULT_COA_DIR))
+}
+
+/// Writes the COA of lab result `lab_id` and returns where it ended up. The
+/// bytes go to a temporary file that is then renamed into place, so a reader
+/// never sees a half-written document. Every upload gets a new name, so the
+/// document it replaces stays intact until the database points elsewhere.
+pub fn save_coa(lab_id: i32, extension: &str, body: &[u8]) -> io::Result<PathBuf> {
+    let dir = coa_dir();
+    fs::create_dir_all(&dir)?;
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let name = format!("lab-result-{}-{}.{}", lab_id, stamp, extension);
+    let tmp = dir.join(format!(".{}.tmp", name));
+    let path = dir.join(name);
+    fs::write(&tmp, body)?;
+    fs::rename(&tmp, &path)?;
+    Ok(path)
+}
+
+/// Removes a document that is no longer referenced. One that is already
+/// gone is not an error.
+pub fn remove_coa(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Removes the documents of lab results whose rows have already been
+/// deleted. Every path is tried, and the first failure is returned.
+pub fn discard_coas(paths: impl IntoIterator<Item = String>) -> io::Result<()> {
+    paths
+        .into_iter()
+        .map(|path| remove_coa(Path::new(&path)))
+        .fold(Ok(()), Result::and)
+}
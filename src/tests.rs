@@ -3,6 +3,24 @@ mod tests {
     use crate::models::*;
     use crate::*;
 
+    use chrono::NaiveDate;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn usd(amount: &str) -> Money {
+        Money::parse(amount, "USD").unwrap()
+    }
+
+    fn grams(amount: f32) -> Weight {
+        Weight::new(amount, WeightUnit::G)
+    }
+
+    fn days_ago(days: i64) -> NaiveDate {
+        chrono::Local::now().naive_local().date() - chrono::Duration::days(days)
+    }
+
     #[test]
     fn product_created_and_deleted() {
         let conn = establish_connection().unwrap();
@@ -39,9 +57,869 @@ mod tests {
             .create(&conn)
             .unwrap();
 
-        let new = NewInventory::new(*_prod.get_id(), 10, 15.0, 1.0).create(&conn);
+        let new = NewInventory::new(*_prod.get_id(), 10, usd("15.00"), grams(1.0)).create(&conn);
+
+        assert!(new.is_ok());
+
+        let deleted = new.unwrap().delete(&conn);
+
+        assert!(deleted.is_ok());
+
+        let _ = _prod.delete(&conn);
+    }
+
+    #[test]
+    fn money_parsed_and_serialized_exactly() {
+        assert_eq!(*usd("15.10").get_cents(), 1510);
+        assert_eq!(*usd("15.1").get_cents(), 1510);
+        assert_eq!(*usd("15.100").get_cents(), 1510);
+        assert_eq!(usd("0.07").to_string(), "0.07 USD");
+        assert!(Money::parse("15.105", "USD").is_err());
+        assert!(Money::parse("1e3", "USD").is_err());
+        assert!(Money::parse("15.10", "dollars").is_err());
+
+        let total = usd("15.10").checked_mul(3).unwrap();
+        assert_eq!(total.to_string(), "45.30 USD");
+        assert_eq!(total.discount(1_000).unwrap().to_string(), "40.77 USD");
+        assert!(total
+            .checked_add(&Money::parse("1", "eur").unwrap())
+            .is_err());
+
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Reggie Kush #16", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let body = format!(
+            r#"{{"product_id": {}, "stock": 1, "price": 15.1, "net_weight": 1.0}}"#,
+            _prod.get_id()
+        );
+        let new: NewInventory = serde_json::from_str(&body).unwrap();
+        let inv = new.create(&conn).unwrap();
+
+        assert_eq!(inv.get_price(), usd("15.10"));
+
+        let json = serde_json::to_value(&inv).unwrap();
+
+        assert_eq!(json["price"], "15.10");
+        assert_eq!(json["currency"], "USD");
+
+        let _ = _prod.delete(&conn);
+    }
+
+    #[test]
+    fn weight_converted_and_priced_per_gram() {
+        let oz = Weight::new(1.0, WeightUnit::Oz);
+
+        assert_eq!(
+            oz.to(WeightUnit::Eighth),
+            Weight::new(8.0, WeightUnit::Eighth)
+        );
+        assert_eq!(*grams(0.5).to(WeightUnit::Mg).get_amount(), 500.0);
+        assert_eq!(usd("200.00").per_gram(&oz), Some(usd("7.14")));
+        assert_eq!(usd("1.00").per_gram(&grams(0.0)), None);
+
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Reggie Kush #17", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let eighth = Weight::new(1.0, WeightUnit::Eighth);
+        let _ = NewInventory::new(*_prod.get_id(), 4, usd("35.00"), eighth).create(&conn);
+
+        let inv = Inventory::with_product_id(&conn, _prod.get_id()).unwrap();
+        let json = serde_json::to_value(&inv[0]).unwrap();
+
+        assert_eq!(json["net_weight_unit"], "eighth");
+        assert_eq!(json["price_per_gram"], "10.00");
+
+        let inv = inv.into_iter().next().unwrap().in_unit(Some(WeightUnit::G));
+        let json = serde_json::to_value(&inv).unwrap();
+
+        assert_eq!(json["net_weight"], 3.5);
+        assert_eq!(json["net_weight_unit"], "g");
+        assert_eq!(json["price_per_gram"], "10.00");
+
+        let _ = _prod.delete(&conn);
+    }
+
+    #[test]
+    fn stock_movements_kept_in_sync() {
+        use crate::errors::ApiError;
+        use crate::pagination::PageParams;
+        use actix_web::http::StatusCode;
+        use actix_web::ResponseError;
+
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Reggie Kush #18", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let inv = NewInventory::new(*_prod.get_id(), 10, usd("15.00"), grams(1.0))
+            .create(&conn)
+            .unwrap();
+        let sale = |quantity: i32| MovementInput {
+            kind: MovementKind::Sale,
+            quantity,
+            reason: "Walk-in sale".to_owned(),
+            user_id: 1,
+        };
+
+        let sold = StockMovement::record(&conn, inv.get_id(), sale(-3)).unwrap();
+
+        assert_eq!(*sold.get_balance(), 7);
+
+        let oversold = StockMovement::record(&conn, inv.get_id(), sale(-8)).unwrap_err();
+
+        assert_eq!(ApiError::from(oversold).status_code(), StatusCode::CONFLICT);
+        assert!(sale(3).clean().is_err());
+
+        let changes = InventoryChanges {
+            stock: Some(5),
+            ..Default::default()
+        };
+        let inv = changes.update(&conn, inv.get_id()).unwrap();
+        let page =
+            StockMovement::of_inventory(&conn, inv.get_id(), &PageParams::default()).unwrap();
+        let kinds = page
+            .get_data()
+            .iter()
+            .map(|m| *m.get_kind())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            kinds,
+            [
+                MovementKind::Receipt,
+                MovementKind::Sale,
+                MovementKind::Adjustment
+            ]
+        );
+        assert_eq!(
+            page.get_data()
+                .iter()
+                .map(|m| m.get_quantity())
+                .sum::<i32>(),
+            *inv.get_stock()
+        );
+        assert!(sql_query("UPDATE stock_movements SET quantity = 1")
+            .execute(&conn)
+            .is_err());
+        assert!(sql_query("DELETE FROM stock_movements")
+            .execute(&conn)
+            .is_err());
+
+        let _ = _prod.delete(&conn);
+
+        assert_eq!(
+            StockMovement::of_inventory(&conn, inv.get_id(), &PageParams::default())
+                .unwrap()
+                .get_data()
+                .len(),
+            page.get_data().len()
+        );
+    }
+
+    #[test]
+    fn order_checked_out_and_cancelled() {
+        use crate::errors::{delete_error, ApiError};
+        use actix_web::http::StatusCode;
+        use actix_web::ResponseError;
+
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Reggie Kush #19", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let eighth = NewInventory::new(*_prod.get_id(), 10, usd("15.10"), grams(3.5))
+            .create(&conn)
+            .unwrap();
+        let oz = NewInventory::new(*_prod.get_id(), 2, usd("99.99"), grams(28.0))
+            .create(&conn)
+            .unwrap();
+        let checkout = |lines: Vec<(i32, i32)>| {
+            let input = CheckoutInput {
+                user_id: 1,
+                lines: lines
+                    .into_iter()
+                    .map(|(inventory_id, quantity)| CheckoutLine {
+                        inventory_id,
+                        quantity,
+                    })
+                    .collect(),
+            };
+            Order::checkout(&conn, input.clean().unwrap())
+        };
+
+        let detail = checkout(vec![(*eighth.get_id(), 3), (*oz.get_id(), 1)]).unwrap();
+        let json = serde_json::to_value(&detail).unwrap();
+
+        assert_eq!(json["total"], "145.29");
+        assert_eq!(json["lines"][0]["line_total"], "45.30");
+        assert_eq!(
+            *Inventory::with_id(&conn, eighth.get_id())
+                .unwrap()
+                .get_stock(),
+            7
+        );
+
+        let oversold = checkout(vec![(*eighth.get_id(), 1), (*oz.get_id(), 2)]).unwrap_err();
+
+        assert_eq!(ApiError::from(oversold).status_code(), StatusCode::CONFLICT);
+        assert_eq!(
+            *Inventory::with_id(&conn, eighth.get_id())
+                .unwrap()
+                .get_stock(),
+            7
+        );
+
+        let ord_id = json["id"].as_i64().unwrap() as i32;
+        let to = |status: OrderStatus| OrderStatusInput { status, user_id: 1 };
+        let cancelled = Order::transition(&conn, &ord_id, to(OrderStatus::Cancelled)).unwrap();
+
+        assert_eq!(*cancelled.get_status(), OrderStatus::Cancelled);
+        assert_eq!(
+            *Inventory::with_id(&conn, eighth.get_id())
+                .unwrap()
+                .get_stock(),
+            10
+        );
+        assert_eq!(
+            *Inventory::with_id(&conn, oz.get_id()).unwrap().get_stock(),
+            2
+        );
+        assert!(Order::transition(&conn, &ord_id, to(OrderStatus::Paid)).is_err());
+
+        let sold = |e| delete_error(e).status_code();
+
+        assert_eq!(sold(oz.delete(&conn).unwrap_err()), StatusCode::CONFLICT);
+        assert_eq!(
+            sold(
+                Product::with_id(&conn, _prod.get_id())
+                    .unwrap()
+                    .delete_cascade(&conn)
+                    .unwrap_err()
+            ),
+            StatusCode::CONFLICT
+        );
+
+        let _ = sql_query("DELETE FROM orders WHERE user_id = 1").execute(&conn);
+        let _ = _prod.delete(&conn);
+    }
+
+    #[test]
+    fn concurrent_checkouts_never_oversell() {
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Reggie Kush #20", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let inv = NewInventory::new(*_prod.get_id(), 10, usd("10.00"), grams(1.0))
+            .create(&conn)
+            .unwrap();
+        let inv_id = *inv.get_id();
+
+        let buyers = (0..4)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    let conn = establish_connection().unwrap();
+                    let input = CheckoutInput {
+                        user_id: 2,
+                        lines: vec![CheckoutLine {
+                            inventory_id: inv_id,
+                            quantity: 3,
+                        }],
+                    };
+                    Order::checkout(&conn, input).is_ok()
+                })
+            })
+            .collect::<Vec<_>>();
+        let sold = buyers
+            .into_iter()
+            .filter_map(|b| b.join().ok())
+            .filter(|ok| *ok)
+            .count() as i32;
+
+        assert_eq!(sold, 3);
+        assert_eq!(*Inventory::with_id(&conn, &inv_id).unwrap().get_stock(), 1);
+
+        let _ = sql_query("DELETE FROM orders WHERE user_id = 2").execute(&conn);
+        let _ = _prod.delete(&conn);
+    }
+
+    #[test]
+    fn low_stock_reported_by_shortfall() {
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Reggie Kush #21", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let stocked = |stock: i32, threshold: i32| {
+            NewInventory::new(*_prod.get_id(), stock, usd("10.00"), grams(1.0))
+                .with_reorder_threshold(threshold)
+                .create(&conn)
+                .unwrap()
+        };
+        let low = stocked(4, 5);
+        let out = stocked(0, 10);
+        let fine = stocked(20, 5);
+
+        let report = serde_json::to_value(LowStockItem::report(&conn).unwrap()).unwrap();
+        let report = report.as_array().unwrap();
+        let position = |inv: &Inventory| {
+            report
+                .iter()
+                .position(|i| i["id"] == serde_json::json!(inv.get_id()))
+        };
+
+        assert!(position(&fine).is_none());
+        assert!(position(&out).unwrap() < position(&low).unwrap());
+        assert_eq!(report[position(&out).unwrap()]["shortfall"], 10);
+
+        let _ = _prod.delete(&conn);
+    }
+
+    #[test]
+    fn similar_products_ranked_by_profile() {
+        let conn = establish_connection().unwrap();
+        let profiled = |label: &str, category: Category, thc: f32, myrcene: f32, stock: i32| {
+            let prod = NewProduct::new(label, category).create(&conn).unwrap();
+            let cnbs = NewCannabis::new(*prod.get_id(), Family::Hybrid, thc, 0.5, thc + 1.0)
+                .create(&conn)
+                .unwrap();
+            let _ = NewTerpene::new(*cnbs.get_id(), myrcene, 0.2, 0.3, 0.1, 0.0).create(&conn);
+            let _ =
+                NewInventory::new(*prod.get_id(), stock, usd("30.00"), grams(3.5)).create(&conn);
+            prod
+        };
+        let origin = profiled("Similar Origin", Category::Flower, 20.0, 1.0, 5);
+        let near = profiled("Similar Near", Category::Flower, 21.0, 1.1, 5);
+        let far = profiled("Similar Far", Category::Flower, 29.0, 0.1, 5);
+        let sold_out = profiled("Similar Sold Out", Category::Flower, 20.0, 1.0, 0);
+        let cart = profiled("Similar Cartridge", Category::Cartridge, 20.0, 1.0, 5);
+
+        let ranked = |weights: SimilarityWeights, category: Option<&Category>| {
+            let similar = SimilarProduct::rank(&conn, origin.get_id(), weights, category, 100);
+            let similar = serde_json::to_value(similar.unwrap()).unwrap();
+            similar
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p["name"].as_str().unwrap().to_owned())
+                .filter(|n| n.starts_with("Similar "))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            ranked(SimilarityWeights::default(), None),
+            vec!["Similar Near", "Similar Far"]
+        );
+        assert_eq!(
+            ranked(SimilarityWeights::default(), Some(&Category::Cartridge)),
+            vec!["Similar Cartridge"]
+        );
+
+        let terpenes_only = SimilarityWeights {
+            thc: 0.0,
+            cbd: 0.0,
+            total_cannabinoids: 0.0,
+            ..Default::default()
+        };
+
+        assert_eq!(ranked(terpenes_only, None)[0], "Similar Near");
+
+        for prod in [origin, near, far, sold_out, cart] {
+            let _ = prod.delete(&conn);
+        }
+    }
+
+    #[test]
+    fn similar_products_compared_on_normalised_scales() {
+        let conn = establish_connection().unwrap();
+        let profiled = |label: &str, thc: f32, terpene: f32| {
+            let prod = NewProduct::new(label, Category::Extract)
+                .create(&conn)
+                .unwrap();
+            let cnbs = NewCannabis::new(*prod.get_id(), Family::Indica, thc, 0.5, thc + 1.0)
+                .create(&conn)
+                .unwrap();
+            let _ = NewTerpene::new(*cnbs.get_id(), terpene, terpene, terpene, terpene, terpene)
+                .create(&conn);
+            let _ = NewInventory::new(*prod.get_id(), 5, usd("50.00"), grams(1.0)).create(&conn);
+            prod
+        };
+        let origin = profiled("Scaled Origin", 20.0, 0.0);
+        let terpy = profiled("Scaled Terpy", 20.0, 6.0);
+        let potent = profiled("Scaled Potent", 28.0, 0.0);
+        let strongest = profiled("Scaled Strongest", 30.0, 0.0);
+
+        // unscaled, the terpene gap (6 on each of five) outweighs the thc
+        // gap of 8; scaled by range it is the smaller difference
+        let similar = serde_json::to_value(
+            SimilarProduct::rank(&conn, origin.get_id(), Default::default(), None, 100).unwrap(),
+        )
+        .unwrap();
+        let names = similar
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["name"].as_str().unwrap().to_owned())
+            .filter(|n| n.starts_with("Scaled "))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            names,
+            vec!["Scaled Terpy", "Scaled Potent", "Scaled Strongest"]
+        );
+
+        for prod in [origin, terpy, potent, strongest] {
+            let _ = prod.delete(&conn);
+        }
+    }
+
+    #[test]
+    fn scheduled_price_takes_effect_when_read() {
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Reggie Kush #22", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let inv = NewInventory::new(*_prod.get_id(), 5, usd("40.00"), grams(3.5))
+            .create(&conn)
+            .unwrap();
+        let changes = InventoryChanges {
+            price_cents: Some(3_800),
+            ..Default::default()
+        };
+        let _ = changes.update(&conn, inv.get_id()).unwrap();
+
+        let friday = chrono::Local::now().naive_local() + chrono::Duration::days(3);
+        let sale = PriceInput {
+            price_cents: 2_999,
+            currency: "USD".to_owned(),
+            effective_from: Some(friday),
+        };
+        let _ = PriceChange::schedule(&conn, inv.get_id(), sale).unwrap();
+
+        assert_eq!(
+            Inventory::with_id(&conn, inv.get_id()).unwrap().get_price(),
+            usd("38.00")
+        );
+
+        let timeline =
+            serde_json::to_value(PriceChange::timeline(&conn, inv.get_id()).unwrap()).unwrap();
+        let flags = |key: &str| {
+            timeline
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p[key].as_bool().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(timeline[0]["price"], "40.00");
+        assert_eq!(flags("current"), [false, true, false]);
+        assert_eq!(flags("scheduled"), [false, false, true]);
+
+        let _ = sql_query(
+            "UPDATE price_history SET effective_from = NOW()
+                           WHERE inventory_id = $1 AND price_cents = 2999",
+        )
+        .bind::<diesel::sql_types::Integer, _>(inv.get_id())
+        .execute(&conn);
+
+        assert_eq!(
+            Inventory::with_id(&conn, inv.get_id()).unwrap().get_price(),
+            usd("29.99")
+        );
+
+        let stored: i64 = crate::schema::inventories::table
+            .find(inv.get_id())
+            .select(crate::schema::inventories::price_cents)
+            .get_result(&conn)
+            .unwrap();
+
+        assert_eq!(stored, 3_800);
+
+        let restock = InventoryChanges {
+            stock: Some(6),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            restock.update(&conn, inv.get_id()).unwrap().get_price(),
+            usd("29.99")
+        );
+        assert_eq!(PriceChange::timeline(&conn, inv.get_id()).unwrap().len(), 3);
+
+        let backdated = PriceInput {
+            price_cents: 100,
+            currency: "USD".to_owned(),
+            effective_from: Some(
+                chrono::NaiveDate::from_ymd_opt(2020, 1, 1)
+                    .unwrap()
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap(),
+            ),
+        };
+        assert!(PriceChange::schedule(&conn, inv.get_id(), backdated).is_err());
+
+        let inv_id = *inv.get_id();
+        let _ = _prod.delete(&conn);
+
+        assert_eq!(PriceChange::timeline(&conn, &inv_id).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn strain_lineage_built_without_cycles() {
+        use crate::errors::ApiError;
+        use actix_web::http::StatusCode;
+        use actix_web::ResponseError;
+
+        let conn = establish_connection().unwrap();
+        let strain = |label: &str, family: Family| {
+            let prod = NewProduct::new(label, Category::Flower)
+                .create(&conn)
+                .unwrap();
+            let cnbs = NewCannabis::new(*prod.get_id(), family, 20.0, 0.5, 21.0)
+                .create(&conn)
+                .unwrap();
+            (prod, *cnbs.get_id())
+        };
+        let parents = |child: i32, parent_ids: Vec<i32>| {
+            Cannabis::set_parents(&conn, &child, ParentsInput { parent_ids })
+        };
+        let (chem, chem_id) = strain("Lineage Chemdawg", Family::Hybrid);
+        let (afghani, afghani_id) = strain("Lineage Afghani", Family::Indica);
+        let (og, og_id) = strain("Lineage OG Kush", Family::Hybrid);
+        let (gsc, gsc_id) = strain("Lineage GSC", Family::Hybrid);
+        let (sherb, sherb_id) = strain("Lineage Sherbet", Family::Indica);
+
+        parents(og_id, vec![chem_id, afghani_id]).unwrap();
+        parents(gsc_id, vec![og_id]).unwrap();
+        parents(sherb_id, vec![afghani_id]).unwrap();
+
+        let tree = serde_json::to_value(Cannabis::lineage(&conn, &gsc_id, 3).unwrap()).unwrap();
+
+        assert_eq!(tree["name"], "Lineage GSC");
+        assert_eq!(tree["parents"][0]["name"], "Lineage OG Kush");
+        assert_eq!(tree["parents"][0]["parents"].as_array().unwrap().len(), 2);
+
+        let shallow = serde_json::to_value(Cannabis::lineage(&conn, &gsc_id, 1).unwrap()).unwrap();
+
+        assert!(shallow["parents"][0]["parents"]
+            .as_array()
+            .unwrap()
+            .is_empty());
+
+        let cycle = parents(chem_id, vec![gsc_id]).unwrap_err();
+
+        assert_eq!(
+            ApiError::from(cycle).status_code(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert!(parents(chem_id, vec![chem_id]).is_err());
+
+        let relatives =
+            serde_json::to_value(Cannabis::relatives(&conn, &sherb_id).unwrap()).unwrap();
+        let names = relatives
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+
+        assert!(names.contains(&"Lineage OG Kush"));
+        assert!(names.contains(&"Lineage GSC"));
+        assert!(!names.contains(&"Lineage Chemdawg"));
+        assert!(Cannabis::relatives(&conn, &chem_id).unwrap().is_empty());
+
+        for prod in [chem, afghani, og, gsc, sherb] {
+            let _ = prod.delete(&conn);
+        }
+    }
+
+    #[test]
+    fn product_updated() {
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Purple Punch #3", Category::Flower)
+            .create(&conn)
+            .unwrap();
+
+        let changes = ProductChanges {
+            name: Some("Purple Punch #4".to_owned()),
+            ..Default::default()
+        };
+        let patched = changes.update(&conn, _prod.get_id()).unwrap();
+
+        assert_eq!(patched.get_name(), "Purple Punch #4");
+
+        let put = NewProduct::new("Purple Punch #5", Category::PreRoll)
+            .update(&conn, _prod.get_id())
+            .unwrap();
+
+        assert_eq!(put.get_name(), "Purple Punch #5");
+
+        let _ = put.delete(&conn);
+    }
+
+    #[test]
+    fn cannabis_patch_checked_against_stored_potency() {
+        use crate::errors::ApiError;
+        use actix_web::{http::StatusCode, ResponseError};
+
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Wedding Cake #9", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let _cnbs = NewCannabis::new(*_prod.get_id(), Family::Hybrid, 22.0, 0.5, 25.0)
+            .create(&conn)
+            .unwrap();
+
+        let changes = CannabisChanges {
+            thc: Some(99.0),
+            ..Default::default()
+        };
+        let err = ApiError::from(changes.update(&conn, _cnbs.get_id()).unwrap_err());
+
+        assert_eq!(err.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            *Cannabis::with_id(&conn, _cnbs.get_id()).unwrap().get_thc(),
+            22.0
+        );
+
+        let changes = CannabisChanges {
+            thc: Some(24.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            *changes.update(&conn, _cnbs.get_id()).unwrap().get_thc(),
+            24.0
+        );
+
+        let _ = _prod.delete(&conn);
+    }
+
+    #[test]
+    fn product_deleted_with_dependents() {
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Gelato #41", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let _cnbs = NewCannabis::new(*_prod.get_id(), Family::Hybrid, 24.0, 0.1, 25.3)
+            .create(&conn)
+            .unwrap();
+        let _ = NewTerpene::new(*_cnbs.get_id(), 0.4, 0.1, 0.2, 0.3, 0.0).create(&conn);
+        let _ =
+            NewBatch::new(*_cnbs.get_id(), days_ago(60), days_ago(30), days_ago(20)).create(&conn);
+        let _ = NewInventory::new(*_prod.get_id(), 5, usd("45.00"), grams(3.5)).create(&conn);
+        let _ = NewInventory::new(*_prod.get_id(), 2, usd("80.00"), grams(7.0)).create(&conn);
+
+        let deleted = _prod.delete_cascade(&conn).unwrap();
+
+        assert_eq!(*deleted.get_cannabis_deleted(), 1);
+        assert_eq!(*deleted.get_terpenes_deleted(), 1);
+        assert_eq!(*deleted.get_batches_deleted(), 1);
+        assert_eq!(*deleted.get_inventories_deleted(), 2);
+    }
+
+    #[test]
+    fn diesel_errors_map_to_status_codes() {
+        use crate::errors::ApiError;
+        use actix_web::{http::StatusCode, ResponseError};
+
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Wedding Cake #2", Category::Flower)
+            .create(&conn)
+            .unwrap();
+
+        let missing = Product::with_id(&conn, &-1).map_err(ApiError::from);
+        assert_eq!(missing.unwrap_err().status_code(), StatusCode::NOT_FOUND);
+
+        let duplicate = NewProduct::new("Wedding Cake #2", Category::Flower)
+            .create(&conn)
+            .map_err(ApiError::from);
+        assert_eq!(duplicate.unwrap_err().status_code(), StatusCode::CONFLICT);
+
+        let orphan = NewInventory::new(-1, 1, usd("10.00"), grams(1.0))
+            .create(&conn)
+            .map_err(ApiError::from);
+        assert_eq!(
+            orphan.unwrap_err().status_code(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+
+        let _ = _prod.delete(&conn);
+    }
+
+    #[actix_rt::test]
+    async fn exhausted_pool_returns_service_unavailable() {
+        use crate::pool::{DbConn, PoolConfig};
+        use actix_rt::time::delay_for;
+        use actix_web::http::{header, StatusCode};
+        use actix_web::test::TestRequest;
+        use actix_web::{FromRequest, ResponseError};
+        use futures::future::{select, Either};
+        use std::time::Duration;
+
+        let config = PoolConfig {
+            max_size: 1,
+            connection_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let pool = config.build(&env::var("DATABASE_URL").unwrap()).unwrap();
+        let _held = pool.get().unwrap();
+
+        // the checkout waits off the worker, so a timer on the same worker
+        // still fires while it is pending
+        let req = TestRequest::default().data(pool.clone()).to_http_request();
+        let timer = delay_for(Duration::from_millis(20));
+        let extract = match select(DbConn::extract(&req), timer).await {
+            Either::Right((_, extract)) => extract,
+            Either::Left(_) => panic!("pool checkout blocked the worker"),
+        };
+        let err = extract.await.err().unwrap();
+
+        assert_eq!(err.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(err
+            .error_response()
+            .headers()
+            .contains_key(header::RETRY_AFTER));
+    }
+
+    #[test]
+    fn products_paginated_by_cursor() {
+        use crate::errors::ApiError;
+        use crate::pagination::PageParams;
+        use actix_web::{http::StatusCode, ResponseError};
+
+        let conn = establish_connection().unwrap();
+        let _prods: Vec<Product> = (1..=3)
+            .map(|n| {
+                NewProduct::new(&format!("Zkittlez #{}", n), Category::Flower)
+                    .create(&conn)
+                    .unwrap()
+            })
+            .collect();
+
+        let params = PageParams {
+            limit: Some(2),
+            sort: Some("-id".to_owned()),
+            ..Default::default()
+        };
+        let first = Product::page(&conn, &params).unwrap();
+
+        assert_eq!(first.get_data().len(), 2);
+        assert!(first.get_next_cursor().is_some());
+
+        let params = PageParams {
+            cursor: first.get_next_cursor().clone(),
+            ..params
+        };
+        let second = Product::page(&conn, &params).unwrap();
+
+        assert!(second.get_data()[0].get_id() < first.get_data()[1].get_id());
+
+        let params = PageParams {
+            sort: Some("name".to_owned()),
+            ..params
+        };
+        assert!(Product::page(&conn, &params).is_err());
+
+        let forged: String = "-id\n1\n1); DROP TABLE products; --"
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let params = PageParams {
+            sort: Some("-id".to_owned()),
+            cursor: Some(forged),
+            ..params
+        };
+        let forged = Product::page(&conn, &params).unwrap_err();
+        assert_eq!(
+            ApiError::from(forged).status_code(),
+            StatusCode::BAD_REQUEST
+        );
+
+        for prod in _prods {
+            let _ = prod.delete(&conn);
+        }
+    }
+
+    #[test]
+    fn products_filtered_by_potency() {
+        use crate::filters::ProductFilter;
+        use actix_web::web::Query;
+
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Durban Poison #8", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let _ = NewCannabis::new(*_prod.get_id(), Family::Sativa, 27.0, 0.2, 28.0).create(&conn);
+
+        let found = |qs: &str| -> bool {
+            Query::<ProductFilter>::from_query(qs)
+                .unwrap()
+                .load(&conn)
+                .unwrap()
+                .get_data()
+                .iter()
+                .any(|p| p.get_id() == _prod.get_id())
+        };
+
+        assert!(found(
+            "category=Flower&family=Sativa&thc_min=26.5&limit=500"
+        ));
+        assert!(!found("family=Sativa&thc_max=20&limit=500"));
+        assert!(!found("category=Edible&limit=500"));
+        assert!(Query::<ProductFilter>::from_query("colour=green").is_err());
+        assert!(Query::<ProductFilter>::from_query("family=Ruderalis").is_err());
+
+        let _ = _prod.delete(&conn);
+    }
+
+    #[test]
+    fn products_found_by_fuzzy_name() {
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("O.G. Kush #69", Category::PreRoll)
+            .create(&conn)
+            .unwrap();
+
+        let matches = ProductMatch::search(&conn, "OG Kush", 50).unwrap();
+        let hit = matches.iter().find(|m| m.get_id() == _prod.get_id());
+
+        assert!(hit.is_some());
+        assert!(*hit.unwrap().get_score() > 0.0);
+
+        let matches = ProductMatch::search(&conn, "o.g. ku", 50).unwrap();
+        let hit = matches.iter().find(|m| m.get_id() == _prod.get_id());
+
+        assert!(*hit.unwrap().is_prefix_match());
+
+        let _ = _prod.delete(&conn);
+    }
+
+    #[test]
+    fn terpenes_created_and_deleted() {
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Tangie #2", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let _cnbs = NewCannabis::new(*_prod.get_id(), Family::Sativa, 19.0, 0.1, 20.0)
+            .create(&conn)
+            .unwrap();
+
+        let input = TerpeneInput {
+            myrcene: 0.4,
+            limonene: 1.2,
+            ..Default::default()
+        };
+        let new = input
+            .clean()
+            .unwrap()
+            .into_new_terpene(*_cnbs.get_id())
+            .create(&conn);
 
         assert!(new.is_ok());
+        assert_eq!(
+            Terpene::with_cannabis_id(&conn, _cnbs.get_id())
+                .unwrap()
+                .len(),
+            1
+        );
 
         let deleted = new.unwrap().delete(&conn);
 
@@ -49,4 +927,602 @@ mod tests {
 
         let _ = _prod.delete(&conn);
     }
+
+    #[test]
+    fn invalid_terpenes_rejected() {
+        let negative = TerpeneInput {
+            pinene: -0.1,
+            ..Default::default()
+        };
+        assert!(negative.clean().is_err());
+
+        let over = TerpeneInput {
+            myrcene: 60.0,
+            caryophyllene: 45.0,
+            ..Default::default()
+        };
+        assert!(over.clean().is_err());
+    }
+
+    #[test]
+    fn batch_created_and_deleted() {
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Blue Dream #12", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let _cnbs = NewCannabis::new(*_prod.get_id(), Family::Hybrid, 21.0, 0.1, 22.0)
+            .create(&conn)
+            .unwrap();
+
+        let input = BatchInput {
+            harvest_date: date(2021, 9, 1),
+            package_date: date(2021, 10, 1),
+            final_test_date: date(2021, 10, 15),
+        };
+        let new = input
+            .clean()
+            .unwrap()
+            .into_new_batch(*_cnbs.get_id())
+            .create(&conn);
+
+        assert!(new.is_ok());
+
+        let batch = new.unwrap();
+
+        assert!(Batch::of_cannabis(&conn, _cnbs.get_id(), batch.get_id()).is_ok());
+        assert!(Batch::of_cannabis(&conn, &-1, batch.get_id()).is_err());
+
+        let deleted = batch.delete(&conn);
+
+        assert!(deleted.is_ok());
+
+        let _ = _prod.delete(&conn);
+    }
+
+    #[test]
+    fn recalled_batch_pulled_from_sale() {
+        use crate::errors::ApiError;
+        use actix_web::http::StatusCode;
+        use actix_web::ResponseError;
+
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Recalled Runtz", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let _other = NewProduct::new("Unrecalled Runtz", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let _cnbs = NewCannabis::new(*_prod.get_id(), Family::Hybrid, 25.0, 0.1, 26.0)
+            .create(&conn)
+            .unwrap();
+        let batch = NewBatch::new(*_cnbs.get_id(), days_ago(60), days_ago(30), days_ago(20))
+            .create(&conn)
+            .unwrap();
+        let packed = NewInventory::new(*_prod.get_id(), 10, usd("35.00"), grams(3.5))
+            .with_batch_id(*batch.get_id())
+            .create(&conn)
+            .unwrap();
+        let loose = NewInventory::new(*_prod.get_id(), 4, usd("35.00"), grams(3.5))
+            .create(&conn)
+            .unwrap();
+        let checkout = |inventory_id: i32| {
+            let input = CheckoutInput {
+                user_id: 3,
+                lines: vec![CheckoutLine {
+                    inventory_id,
+                    quantity: 2,
+                }],
+            };
+            Order::checkout(&conn, input)
+        };
+
+        assert!(
+            NewInventory::new(*_other.get_id(), 1, usd("1.00"), grams(1.0))
+                .with_batch_id(*batch.get_id())
+                .create(&conn)
+                .is_err()
+        );
+
+        checkout(*packed.get_id()).unwrap();
+        let recall = RecallInput {
+            reason: "Failed microbial retest".to_owned(),
+        };
+        let report = Batch::recall(&conn, batch.get_id(), recall.clean().unwrap()).unwrap();
+
+        assert_eq!(*report.get_units_on_hand(), 8);
+        assert_eq!(report.get_customers().len(), 1);
+        assert!(Batch::with_id(&conn, batch.get_id())
+            .unwrap()
+            .get_recalled_at()
+            .is_some());
+        assert!(Inventory::with_id(&conn, packed.get_id())
+            .unwrap()
+            .is_recalled());
+
+        let listed = Inventory::with_product_id(&conn, _prod.get_id()).unwrap();
+        let listed = serde_json::to_value(listed).unwrap();
+
+        assert_eq!(listed.as_array().unwrap().len(), 1);
+        assert_eq!(listed[0]["id"], *loose.get_id());
+
+        let blocked = checkout(*packed.get_id()).unwrap_err();
+
+        assert_eq!(ApiError::from(blocked).status_code(), StatusCode::CONFLICT);
+        assert!(checkout(*loose.get_id()).is_ok());
+
+        let again = RecallInput {
+            reason: "Again".to_owned(),
+        };
+        let again = Batch::recall(&conn, batch.get_id(), again).unwrap_err();
+
+        assert_eq!(ApiError::from(again).status_code(), StatusCode::CONFLICT);
+        assert!(
+            NewInventory::new(*_prod.get_id(), 1, usd("1.00"), grams(1.0))
+                .with_batch_id(*batch.get_id())
+                .create(&conn)
+                .is_err()
+        );
+
+        let _ = sql_query("DELETE FROM orders WHERE user_id = 3").execute(&conn);
+        let _ = _prod.delete(&conn);
+        let _ = _other.delete(&conn);
+    }
+
+    #[test]
+    fn expired_stock_left_off_menu() {
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Expiring Gummies", Category::Edible)
+            .create(&conn)
+            .unwrap();
+        let _cnbs = NewCannabis::new(*_prod.get_id(), Family::Hybrid, 10.0, 0.0, 10.0)
+            .create(&conn)
+            .unwrap();
+        let packed = |package_days_ago: i64| {
+            let batch = NewBatch::new(
+                *_cnbs.get_id(),
+                days_ago(package_days_ago + 10),
+                days_ago(package_days_ago),
+                days_ago(package_days_ago - 5),
+            )
+            .create(&conn)
+            .unwrap();
+            NewInventory::new(*_prod.get_id(), 10, usd("20.00"), grams(100.0))
+                .with_batch_id(*batch.get_id())
+                .create(&conn)
+                .unwrap()
+        };
+        let _ = ShelfLife::set(&conn, Category::Edible, ShelfLifeInput { days: 180 });
+        let expired = packed(200);
+        let soon = packed(170);
+        let fresh = packed(10);
+
+        let expiring = serde_json::to_value(ExpiringInventory::within(&conn, 30).unwrap()).unwrap();
+        let days_left = |inv: &Inventory| {
+            expiring
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|e| e["id"] == *inv.get_id())
+                .map(|e| e["days_remaining"].clone())
+        };
+
+        assert_eq!(days_left(&expired), Some(serde_json::json!(-20)));
+        assert_eq!(days_left(&soon), Some(serde_json::json!(10)));
+        assert_eq!(days_left(&fresh), None);
+
+        let menu = serde_json::to_value(Inventory::with_product_id(&conn, _prod.get_id()).unwrap())
+            .unwrap();
+        let menu = menu
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| i["id"].as_i64().unwrap() as i32)
+            .collect::<Vec<_>>();
+
+        assert_eq!(menu, vec![*soon.get_id(), *fresh.get_id()]);
+
+        let input = CheckoutInput {
+            user_id: 4,
+            lines: vec![CheckoutLine {
+                inventory_id: *expired.get_id(),
+                quantity: 1,
+            }],
+        };
+
+        assert!(Order::checkout(&conn, input).is_err());
+
+        let _ = _prod.delete(&conn);
+    }
+
+    #[test]
+    fn lab_result_checked_against_label() {
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Lab Tested Haze", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let _cnbs = NewCannabis::new(*_prod.get_id(), Family::Sativa, 20.0, 0.5, 21.0)
+            .create(&conn)
+            .unwrap();
+        let batch = NewBatch::new(
+            *_cnbs.get_id(),
+            date(2021, 9, 1),
+            date(2021, 10, 1),
+            date(2021, 10, 15),
+        )
+        .create(&conn)
+        .unwrap();
+        let input = |thc: f32| LabResultInput {
+            lab_name: "Steep Hill".to_owned(),
+            tested_on: date(2021, 10, 15),
+            thc,
+            cbd: 0.4,
+            total_cannabinoids: thc + 1.0,
+            terpenes: TerpeneInput {
+                myrcene: 0.8,
+                ..Default::default()
+            },
+            pesticides: TestOutcome::Pass,
+            heavy_metals: TestOutcome::Pass,
+            microbials: TestOutcome::Fail,
+            residual_solvents: TestOutcome::Pass,
+        };
+
+        let close = input(21.5).clean().unwrap();
+        let close = LabResult::record(&conn, batch.get_id(), close).unwrap();
+        let inflated = LabResult::record(&conn, batch.get_id(), input(16.0)).unwrap();
+
+        assert!(!close.passed());
+        assert!(close.potency_check(&conn, 10.0).unwrap().within_tolerance());
+        assert!(!inflated
+            .potency_check(&conn, 10.0)
+            .unwrap()
+            .within_tolerance());
+        assert!(inflated
+            .potency_check(&conn, 25.0)
+            .unwrap()
+            .within_tolerance());
+        assert_eq!(
+            LabResult::with_batch_id(&conn, batch.get_id())
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(input(120.0).clean().is_err());
+
+        env::set_var("COA_STORAGE_DIR", env::temp_dir().join("buds-coa-test"));
+        let attach = |body: &[u8]| {
+            let saved = storage::save_coa(*close.get_id(), "pdf", body).unwrap();
+            LabResult::attach_coa(
+                &conn,
+                close.get_id(),
+                &saved.to_string_lossy(),
+                "application/pdf",
+            )
+            .unwrap()
+        };
+        let (first, replaced) = attach(b"%PDF-1.4");
+
+        assert!(replaced.is_none());
+
+        let (second, replaced) = attach(b"%PDF-1.7");
+
+        assert_eq!(replaced.as_deref(), first.get_coa_path());
+        assert_eq!(
+            std::fs::read(second.get_coa_path().unwrap()).unwrap(),
+            b"%PDF-1.7"
+        );
+
+        storage::discard_coas(replaced).unwrap();
+        let owner = CoaOwner::Product(*_prod.get_id());
+        let (_, stored) =
+            LabResult::delete_with_coas(&conn, owner, || _prod.delete(&conn)).unwrap();
+
+        assert_eq!(
+            stored.iter().map(String::as_str).collect::<Vec<_>>(),
+            [second.get_coa_path().unwrap()]
+        );
+
+        storage::discard_coas(stored).unwrap();
+
+        assert!(!std::path::Path::new(first.get_coa_path().unwrap()).exists());
+        assert!(!std::path::Path::new(second.get_coa_path().unwrap()).exists());
+    }
+
+    #[test]
+    fn batch_dates_out_of_order_rejected() {
+        let input = BatchInput {
+            harvest_date: date(2021, 10, 2),
+            package_date: date(2021, 10, 1),
+            final_test_date: date(2021, 10, 15),
+        };
+        assert!(input.clean().is_err());
+
+        let input = BatchInput {
+            harvest_date: date(2021, 9, 1),
+            package_date: date(2021, 10, 20),
+            final_test_date: date(2021, 10, 15),
+        };
+        assert!(input.clean().is_err());
+    }
+
+    #[test]
+    fn product_detail_expanded() {
+        use crate::filters::ExpandParams;
+
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Sour Diesel #7", Category::Flower)
+            .create(&conn)
+            .unwrap();
+        let _cnbs = NewCannabis::new(*_prod.get_id(), Family::Sativa, 23.0, 0.2, 24.0)
+            .create(&conn)
+            .unwrap();
+        let _ = NewTerpene::new(*_cnbs.get_id(), 0.5, 0.1, 0.3, 0.2, 0.0).create(&conn);
+        let _ = NewInventory::new(*_prod.get_id(), 8, usd("35.00"), grams(3.5)).create(&conn);
+        let batch = NewBatch::new(*_cnbs.get_id(), days_ago(60), days_ago(30), days_ago(20))
+            .create(&conn)
+            .unwrap();
+        let _ = NewInventory::new(*_prod.get_id(), 4, usd("40.00"), grams(3.5))
+            .with_batch_id(*batch.get_id())
+            .create(&conn);
+        let recall = RecallInput {
+            reason: "Pesticide residue".to_owned(),
+        };
+        let _ = Batch::recall(&conn, batch.get_id(), recall.clean().unwrap());
+
+        let params = ExpandParams {
+            expand: Some("terpenes,inventory".to_owned()),
+            unit: Some(WeightUnit::Eighth),
+        };
+        let detail = ProductDetail::load(&conn, _prod.get_id(), &params.expand().unwrap());
+        let json = serde_json::to_value(detail.unwrap()).unwrap();
+
+        assert_eq!(json["name"], "Sour Diesel #7");
+        assert_eq!(json["cannabis"][0]["terpenes"].as_array().unwrap().len(), 1);
+        assert!(json["cannabis"][0].get("batches").is_none());
+        assert_eq!(json["inventory"].as_array().unwrap().len(), 1);
+        assert_eq!(json["inventory"][0]["net_weight_unit"], "eighth");
+        assert_eq!(json["inventory"][0]["price_per_gram"], "10.00");
+
+        let params = ExpandParams {
+            expand: Some("lineage".to_owned()),
+            ..Default::default()
+        };
+        assert!(params.expand().is_err());
+
+        let _ = _prod.delete(&conn);
+    }
+
+    #[test]
+    fn body_accepted_as_form_or_json() {
+        use crate::payload::FormOrJson;
+        use actix_web::http::{header, StatusCode};
+        use actix_web::rt::System;
+        use actix_web::test::TestRequest;
+        use actix_web::{FromRequest, ResponseError};
+
+        let extract = |content_type: &str, body: &'static str| {
+            let (req, mut payload) = TestRequest::default()
+                .header(header::CONTENT_TYPE, content_type)
+                .set_payload(body)
+                .to_http_parts();
+            System::new("test").block_on(FormOrJson::<NewProduct>::from_request(&req, &mut payload))
+        };
+
+        let json = extract(
+            "application/json",
+            r#"{"name": "Runtz", "category": "Flower"}"#,
+        );
+        assert_eq!(json.ok().unwrap().name, "Runtz");
+
+        let form = extract(
+            "application/x-www-form-urlencoded",
+            "name=Runtz&category=PreRoll",
+        );
+        assert_eq!(form.ok().unwrap().name, "Runtz");
+
+        let missing = extract("application/json", r#"{"name": "Runtz"}"#);
+        let err = missing.err().unwrap();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        assert!(err.to_string().contains("category"));
+
+        let xml = extract("text/xml", "<product/>");
+        assert_eq!(
+            xml.err().unwrap().status_code(),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+    }
+
+    #[test]
+    fn invalid_input_reported_by_field() {
+        use crate::errors::ApiError;
+        use actix_web::http::StatusCode;
+        use actix_web::ResponseError;
+
+        let fields = |errors: validation::FieldErrors| {
+            serde_json::to_value(errors.get_errors())
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|e| e["field"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert!(NewProduct::new("Runtz", Category::Flower).clean().is_ok());
+        assert_eq!(
+            fields(
+                NewProduct::new("  ", Category::Flower)
+                    .clean()
+                    .err()
+                    .unwrap()
+            ),
+            ["name"]
+        );
+        assert!(NewProduct::new(&"x".repeat(129), Category::Flower)
+            .clean()
+            .is_err());
+
+        let padded = format!("  Runtz{}", " ".repeat(130));
+        let trimmed = NewProduct::new(&padded, Category::Flower).clean().unwrap();
+
+        assert_eq!(trimmed.name, "Runtz");
+
+        let potent = NewCannabis::new(1, Family::Hybrid, 250.0, -1.0, 20.0).clean();
+        assert_eq!(fields(potent.err().unwrap()), ["thc", "cbd"]);
+
+        let over = NewCannabis::new(1, Family::Hybrid, 20.0, 5.0, 24.0).clean();
+        assert_eq!(fields(over.err().unwrap()), ["total_cannabinoids"]);
+        assert!(NewCannabis::new(1, Family::Hybrid, 20.1, 0.2, 20.3)
+            .clean()
+            .is_ok());
+
+        let inv = NewInventory::new(1, -1, usd("0"), grams(0.0)).clean();
+        let err = ApiError::from(inv.err().unwrap());
+
+        assert_eq!(err.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(err.to_string(), "`stock` must not be negative. `price` must be greater than zero. `net_weight` must be greater than zero.");
+
+        let changes = InventoryChanges {
+            stock: Some(3),
+            ..Default::default()
+        };
+        assert!(changes.clean().is_ok());
+
+        let body = r#"{"product_id": 1, "stock": 2, "price": 10.0, "net_weight": 1.0}"#;
+        let put = serde_json::from_str::<InventoryReplacement>(body).unwrap();
+
+        assert_eq!(fields(put.clean().err().unwrap()), ["reorder_threshold"]);
+
+        let batch_id = |body| {
+            serde_json::from_str::<InventoryChanges>(body)
+                .unwrap()
+                .batch_id
+        };
+
+        assert_eq!(batch_id(r#"{"batch_id": null}"#), Some(None));
+        assert_eq!(batch_id(r#"{"batch_id": 7}"#), Some(Some(7)));
+        assert_eq!(batch_id("{}"), None);
+    }
+
+    #[actix_rt::test]
+    async fn oversized_import_rejected_as_json() {
+        use crate::handlers::post_products_import;
+        use crate::imports::MAX_IMPORT_BYTES;
+        use crate::pool::PoolConfig;
+        use actix_web::http::StatusCode;
+        use actix_web::{test, web, App};
+
+        let pool = PoolConfig::default()
+            .build(&env::var("DATABASE_URL").unwrap())
+            .unwrap();
+        let mut app = test::init_service(
+            App::new().data(pool).service(
+                web::resource("/imports/products")
+                    .app_data(web::PayloadConfig::new(MAX_IMPORT_BYTES))
+                    .route(web::post().to(post_products_import)),
+            ),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/imports/products?dry_run=true")
+            .set_payload(vec![b'a'; MAX_IMPORT_BYTES + 1])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["status"], 413);
+    }
+
+    #[test]
+    fn products_imported_from_csv() {
+        use crate::imports::import_products;
+
+        let conn = establish_connection().unwrap();
+        let csv = "name,category,family,thc,cbd,total_cannabinoids,stock,price,net_weight
+                   Import Haze #1,Flower,Sativa,18.5,0.3,19.2,12,35.0,3.5
+                   Import Grinder #1,Accessory,,,,,4,20.0,150.0";
+        let bad = format!("{}\nImport Haze #2,Flower,Sativa,18.5,,19.2,1,1.0,1.0", csv);
+
+        let dry = import_products(&conn, csv.as_bytes(), true).unwrap();
+
+        assert!(dry.is_ok());
+        assert_eq!(*dry.get_created(), 2);
+
+        let failed = import_products(&conn, bad.as_bytes(), false).unwrap();
+
+        assert!(!failed.is_ok());
+        assert_eq!(*failed.get_rows(), 3);
+
+        let search = |n: &str| ProductMatch::search(&conn, n, 50).unwrap();
+        assert!(search("Import Haze #1")
+            .iter()
+            .all(|m| !m.is_prefix_match()));
+
+        let done = import_products(&conn, csv.as_bytes(), false).unwrap();
+
+        assert_eq!(*done.get_created(), 2);
+
+        let hits = search("Import Haze #1");
+        let prod = hits.iter().find(|m| *m.is_prefix_match()).unwrap();
+        let inv = Inventory::with_product_id(&conn, prod.get_id()).unwrap();
+
+        assert_eq!(inv.len(), 1);
+
+        for m in search("Import Haze #1")
+            .iter()
+            .chain(search("Import Grinder #1").iter())
+        {
+            if *m.is_prefix_match() {
+                let _ = Product::with_id(&conn, m.get_id()).unwrap().delete(&conn);
+            }
+        }
+    }
+
+    #[test]
+    fn inventory_exported_by_category() {
+        use crate::export::{stream_rows, ExportFormat, ExportParams, INVENTORY_EXPORT_SELECT};
+        use crate::pool::{DbConn, PoolConfig};
+        use actix_web::rt::System;
+        use actix_web::test::TestRequest;
+        use actix_web::FromRequest;
+        use futures::TryStreamExt;
+
+        let conn = establish_connection().unwrap();
+        let _prod = NewProduct::new("Export Rosin #1", Category::Extract)
+            .create(&conn)
+            .unwrap();
+        let _ = NewInventory::new(*_prod.get_id(), 3, usd("60.00"), grams(1.0)).create(&conn);
+
+        let pool = PoolConfig::default()
+            .build(&env::var("DATABASE_URL").unwrap())
+            .unwrap();
+        let req = TestRequest::default().data(pool).to_http_request();
+        let params = ExportParams {
+            format: ExportFormat::Csv,
+            category: Some(Category::Extract),
+        };
+
+        let export = |category: Option<Category>| {
+            let (req, format) = (req.clone(), params.format);
+            let body = System::new("test").block_on(async move {
+                let db = DbConn::extract(&req).await.ok().unwrap();
+                stream_rows::<InventoryResponse>(db, INVENTORY_EXPORT_SELECT, category, format)
+                    .map_ok(|chunk| chunk.to_vec())
+                    .try_concat()
+                    .await
+            });
+            String::from_utf8(body.ok().unwrap()).unwrap()
+        };
+        let body = export(params.category);
+
+        assert!(body.starts_with(
+            "id,product_id,name,category,stock,price,currency,net_weight,net_weight_unit,price_per_gram"
+        ));
+        assert!(body.contains("Export Rosin #1,Extract,3,60.00,USD,1.0,g,60.00"));
+        assert!(body.lines().skip(1).all(|l| l.contains(",Extract,")));
+        assert!(export(None).contains("Export Rosin #1,Extract,"));
+
+        let _ = _prod.delete(&conn);
+    }
 }
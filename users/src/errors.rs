@@ -0,0 +1,9 @@
+use super::FormError;
+
+pub use common::errors::ApiError;
+
+impl From<FormError> for ApiError {
+    fn from(e: FormError) -> Self {
+        ApiError::UnprocessableEntity(e.to_string())
+    }
+}
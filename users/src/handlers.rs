@@ -1,32 +1,26 @@
+use super::errors::ApiError;
+use super::payload::FormOrJson;
+use super::pool::DbConn;
+use super::NewUserInput;
 use super::{Cleanable, Creatable, Hashable, Verifiable};
-use super::{DbPool, NewUserInput};
 
-use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use actix_web::{post, web, HttpResponse, Result};
 
 use serde_json::json;
 
 #[post("/users/register")]
 pub async fn register_handler(
-    pool: web::Data<DbPool>,
-    form: web::Form<NewUserInput>,
-) -> impl Responder {
-    let conn = pool.get().expect("Could not get connection from pool.");
-    let cleaned = form.into_inner().clean();
+    conn: DbConn,
+    body: FormOrJson<NewUserInput>,
+) -> Result<HttpResponse, ApiError> {
+    let usr = body.into_inner().clean()?;
 
-    match cleaned {
-        Err(e) => Ok(HttpResponse::InternalServerError()
-            .json(json!({"status": 500, "message": e.to_string()}))),
-
-        Ok(usr) => web::block(move || {
-            usr.hash_password()
-                .verify(&conn)
-                .and_then(|nu| nu.create(&conn))
-        })
-        .await
-        .map(|usr| HttpResponse::Ok().json(json!({"status": 200, "data": usr})))
-        .map_err(|e| {
-            HttpResponse::InternalServerError()
-                .json(json!({"status": 500, "message": e.to_string()}))
-        }),
-    }
+    web::block(move || {
+        usr.hash_password()
+            .verify(&conn)
+            .and_then(|nu| nu.create(&conn))
+    })
+    .await
+    .map(|usr| HttpResponse::Ok().json(json!({"status": 200, "data": usr})))
+    .map_err(ApiError::from)
 }
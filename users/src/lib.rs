@@ -4,14 +4,16 @@ extern crate diesel;
 use self::models::*;
 use self::schema::users::dsl::users;
 
+pub mod errors;
 pub mod handlers;
 mod models;
 mod schema;
 mod tests;
 
+pub use common::{payload, pool, DbPool};
+
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
-use diesel::r2d2::ConnectionManager;
 use diesel::result::Error;
 use diesel::{Connection, RunQueryDsl};
 
@@ -21,8 +23,6 @@ use sha_crypt::{sha512_simple, Sha512Params};
 
 use std::string::ToString;
 
-pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
-
 #[derive(Debug, Deserialize, Serialize)]
 pub enum FormError {
     EmptyField,
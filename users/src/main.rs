@@ -1,10 +1,8 @@
 use users::handlers::*;
+use users::pool::PoolConfig;
 
 use actix_web::{web, App, HttpServer};
 
-use diesel::pg::PgConnection;
-use diesel::r2d2::ConnectionManager;
-
 use handlebars::Handlebars;
 
 use std::env;
@@ -13,9 +11,8 @@ use std::env;
 async fn main() -> std::io::Result<()> {
     // set up db pool
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
-    let pool = r2d2::Pool::builder()
-        .build(manager)
+    let pool = PoolConfig::from_env()
+        .build(&database_url)
         .expect("Could not create pool.");
 
     // set up template rendering
@@ -31,12 +31,18 @@ impl NewUser {
     pub fn _verify_username(self, conn: &PgConnection) -> Result<NewUser, Error> {
         let usrs: Vec<User> = users::table
             .filter(users::username.eq(self.get_username()))
-            .get_results(conn)
-            .expect("Couldn't verify username.");
+            .get_results(conn)?;
 
         match usrs.is_empty() {
             true => Ok(self),
-            false => Err(Error::NotFound),
+            false => {
+                let info: Box<dyn DatabaseErrorInformation + Send + Sync> =
+                    Box::new(format!("Username {} already exists.", self.get_username()));
+                Err(Error::DatabaseError(
+                    DatabaseErrorKind::UniqueViolation,
+                    info,
+                ))
+            }
         }
     }
 